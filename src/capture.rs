@@ -0,0 +1,334 @@
+// Powerlink Analyzer - Analyze Ethernet POWERLINK Network Traffic
+// Copyright (C) 2016, Thomas Keh
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, version 3 of the License.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The Capture module opens pcap and pcapng files, compressed or from stdin, as a common
+//! source of packets.
+
+use std::cell::Cell;
+use std::fmt;
+use std::fs::File;
+use std::io::{self,BufRead,BufReader,Cursor,Read,Seek,SeekFrom};
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool,Ordering};
+use std::thread;
+use std::time::Duration;
+use flate2::bufread::MultiGzDecoder;
+use ruzstd::{FrameDecoder,StreamingDecoder};
+use xz2::bufread::XzDecoder;
+use pcapfile::{self,PcapReader};
+use pcapng::{PcapngReader,Section,Interface,Record};
+use frame::LinkType;
+
+const PCAPNG_SECTION_HEADER: u32 = 0x0A0D_0D0A;
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+const XZ_MAGIC: [u8; 6] = [0xFD, 0x37, 0x7A, 0x58, 0x5A, 0x00];
+/// Length of the longest magic number.
+const MAGIC_LEN: usize = 6;
+/// Time to wait for a followed file to grow.
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Timestamp resolution of a capture.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resolution {
+	/// 10^-n seconds
+	Decimal(u8),
+	/// 2^-n seconds
+	Binary(u8),
+}
+
+impl Resolution {
+
+	/// Decodes the value of the pcapng `if_tsresol` option.
+	pub fn from_tsresol(value: u8) -> Resolution {
+		if value & 0x80 == 0 {
+			Resolution::Decimal(value)
+		} else {
+			Resolution::Binary(value & 0x7F)
+		}
+	}
+
+	/// Encodes the resolution as value of the pcapng `if_tsresol` option.
+	pub fn tsresol(&self) -> u8 {
+		match *self {
+			Resolution::Decimal(n) => n,
+			Resolution::Binary(n) => n | 0x80,
+		}
+	}
+
+	pub fn nanoseconds(&self) -> f64 {
+		match *self {
+			Resolution::Decimal(n) => 1e9 / 10f64.powi(n as i32),
+			Resolution::Binary(n) => 1e9 / 2f64.powi(n as i32),
+		}
+	}
+
+}
+
+impl Default for Resolution {
+	/// Microseconds, the default of pcap and pcapng.
+	fn default() -> Self {
+		Resolution::Decimal(6)
+	}
+}
+
+impl fmt::Display for Resolution {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			Resolution::Decimal(0) => write!(f, "1s"),
+			Resolution::Decimal(3) => write!(f, "1ms"),
+			Resolution::Decimal(6) => write!(f, "1µs"),
+			Resolution::Decimal(9) => write!(f, "1ns"),
+			Resolution::Decimal(n) => write!(f, "10^-{}s", n),
+			Resolution::Binary(n) => write!(f, "2^-{}s ({:.3}ns)", n, self.nanoseconds()),
+		}
+	}
+}
+
+/// Reads packets from a capture stream of either format.
+enum Reader {
+	Pcap(PcapReader<Box<dyn BufRead>>),
+	Pcapng(PcapngReader<Box<dyn BufRead>>),
+}
+
+/// A capture file or stdin, optionally compressed with gzip, zstd or xz.
+pub struct Source {
+	reader: Reader,
+	/// Bytes read from the file before decompression.
+	bytes_read: Rc<Cell<u64>>,
+	/// Size of the file before decompression, unknown for stdin.
+	total_bytes: Option<u64>,
+	compressed: bool,
+	/// Bytes of the file that were skipped when resuming, minus the replayed headers.
+	skipped: u64,
+}
+
+impl Source {
+
+	/// Opens a capture file, or stdin if the path is `-`. Compression and capture format
+	/// are detected from their magic numbers.
+	pub fn open(path: &Path) -> io::Result<Source> {
+
+		let (input, total_bytes): (Box<dyn Read>, Option<u64>) = if path == Path::new("-") {
+			(Box::new(io::stdin()), None)
+		} else {
+			let file = File::open(path)?;
+			let len = file.metadata()?.len();
+			(Box::new(file), Some(len))
+		};
+		Source::from_input(input, total_bytes)
+	}
+
+	/// Opens a capture file that is still being written. At the end of the file, reading
+	/// waits for more data until `stop` is set. With a resume point from `resume_point`,
+	/// reading continues at its offset instead of at the start.
+	pub fn follow(path: &Path, stop: &'static AtomicBool, resume: Option<(u64, &[u8])>) -> io::Result<Source> {
+		let mut file = File::open(path)?;
+		let (offset, headers) = match resume {
+			Some(resume) => resume,
+			None => return Source::from_input(Box::new(FollowReader { file, stop }), None),
+		};
+		if file.metadata()?.len() < offset {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, "the file is shorter than the part analyzed already"));
+		}
+		file.seek(SeekFrom::Start(offset))?;
+		// The headers let the reader know the format and interfaces of the skipped part.
+		let input = Cursor::new(headers.to_vec()).chain(FollowReader { file, stop });
+		let mut source = Source::from_input(Box::new(input), None)?;
+		source.skipped = offset - headers.len() as u64;
+		Ok(source)
+	}
+
+	fn from_input(input: Box<dyn Read>, total_bytes: Option<u64>) -> io::Result<Source> {
+
+		let bytes_read = Rc::new(Cell::new(0));
+		let input = BufReader::new(CountingReader { inner: input, count: bytes_read.clone() });
+
+		let (magic, input) = peek_magic(Box::new(input))?;
+		let compressed = magic.starts_with(&GZIP_MAGIC) || magic.starts_with(&ZSTD_MAGIC) || magic.starts_with(&XZ_MAGIC);
+		let input: Box<dyn BufRead> = if magic.starts_with(&GZIP_MAGIC) {
+			Box::new(BufReader::new(MultiGzDecoder::new(input)))
+		} else if magic.starts_with(&ZSTD_MAGIC) {
+			Box::new(BufReader::new(ZstdReader::new(input)?))
+		} else if magic.starts_with(&XZ_MAGIC) {
+			Box::new(BufReader::new(XzDecoder::new_multi_decoder(input)))
+		} else {
+			input
+		};
+
+		let (magic, input) = peek_magic(input)?;
+		let le = u32::from_le_bytes([magic[0], magic[1], magic[2], magic[3]]);
+		let be = u32::from_be_bytes([magic[0], magic[1], magic[2], magic[3]]);
+
+		let reader = if le == PCAPNG_SECTION_HEADER {
+			Reader::Pcapng(PcapngReader::new(input))
+		} else if [le, be].iter().any(|&m| m == pcapfile::MAGIC_MICRO || m == pcapfile::MAGIC_NANO) {
+			Reader::Pcap(PcapReader::new(input)?)
+		} else {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, "not a pcap or pcapng file"));
+		};
+
+		Ok(Source { reader, bytes_read, total_bytes, compressed, skipped: 0 })
+	}
+
+	/// Returns the next packet or `None` at the end of the file.
+	pub fn next_record(&mut self) -> io::Result<Option<Record>> {
+		match self.reader {
+			Reader::Pcap(ref mut reader) => reader.next_record(),
+			Reader::Pcapng(ref mut reader) => reader.next_record(),
+		}
+	}
+
+	/// Sections read so far. Only pcapng files have sections.
+	pub fn sections(&self) -> &[Section] {
+		match self.reader {
+			Reader::Pcap(_) => &[],
+			Reader::Pcapng(ref reader) => reader.sections(),
+		}
+	}
+
+	/// Interfaces read so far.
+	pub fn interfaces(&self) -> &[Interface] {
+		match self.reader {
+			Reader::Pcap(ref reader) => reader.interfaces(),
+			Reader::Pcapng(ref reader) => reader.interfaces(),
+		}
+	}
+
+	/// Returns the link-layer type of an interface if it is supported.
+	pub fn link_type(&self, interface_id: usize) -> Option<LinkType> {
+		self.interfaces().get(interface_id).and_then(|i| LinkType::from_dlt(i.link_type as i32))
+	}
+
+	/// Fails if none of the interfaces read so far has a supported link-layer type. A source
+	/// without interfaces yet passes.
+	pub fn check_link_types(&self, path: &Path) -> Result<(), String> {
+		let link_types: Vec<u16> = self.interfaces().iter().map(|i| i.link_type).collect();
+		if link_types.is_empty() || link_types.iter().any(|&t| LinkType::from_dlt(t as i32).is_some()) {
+			return Ok(());
+		}
+		let names: Vec<String> = link_types.iter().map(|t| t.to_string()).collect();
+		Err(format!("Unsupported link-layer type {} in {}. Supported are EN10MB, LINUX_SLL and LINUX_SLL2.", names.join(", "), path.display()))
+	}
+
+	/// Returns the coarsest timestamp resolution of all interfaces read so far.
+	pub fn resolution(&self) -> Option<Resolution> {
+		self.interfaces().iter()
+			.map(|i| i.resolution)
+			.fold(None, |coarsest, r| match coarsest {
+				Some(c) if c.nanoseconds() >= r.nanoseconds() => Some(c),
+				_ => Some(r),
+			})
+	}
+
+	/// Returns the file offset after the last record and the headers needed to continue
+	/// reading there with `follow`. Compressed files cannot be continued at an offset.
+	pub fn resume_point(&self) -> Option<(u64, Vec<u8>)> {
+		if self.compressed {
+			return None;
+		}
+		let (position, headers) = match self.reader {
+			Reader::Pcap(ref reader) => (reader.position(), reader.headers()),
+			Reader::Pcapng(ref reader) => (reader.position(), reader.headers()),
+		};
+		Some((self.skipped + position, headers.to_vec()))
+	}
+
+	/// Returns the bytes read so far and the file size, both before decompression.
+	pub fn progress(&self) -> (u64, Option<u64>) {
+		(self.bytes_read.get(), self.total_bytes)
+	}
+
+}
+
+/// Counts the bytes read from the underlying file.
+struct CountingReader<R> {
+	inner: R,
+	count: Rc<Cell<u64>>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let n = self.inner.read(buf)?;
+		self.count.set(self.count.get() + n as u64);
+		Ok(n)
+	}
+}
+
+/// Reads a growing file. End of file is only reported once `stop` is set, so that records
+/// which are not completely written yet are waited for instead of being truncated.
+struct FollowReader {
+	file: File,
+	stop: &'static AtomicBool,
+}
+
+impl Read for FollowReader {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		loop {
+			let n = self.file.read(buf)?;
+			if n > 0 || buf.is_empty() || self.stop.load(Ordering::SeqCst) {
+				return Ok(n);
+			}
+			thread::sleep(FOLLOW_POLL_INTERVAL);
+		}
+	}
+}
+
+/// Decodes all concatenated frames of a zstd stream.
+struct ZstdReader {
+	decoder: Option<StreamingDecoder<Box<dyn BufRead>, FrameDecoder>>,
+}
+
+impl ZstdReader {
+	fn new(input: Box<dyn BufRead>) -> io::Result<Self> {
+		let decoder = StreamingDecoder::new(input).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+		Ok(ZstdReader { decoder: Some(decoder) })
+	}
+}
+
+impl Read for ZstdReader {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		loop {
+			let n = match self.decoder {
+				Some(ref mut decoder) => decoder.read(buf)?,
+				None => return Ok(0),
+			};
+			if n > 0 || buf.is_empty() {
+				return Ok(n);
+			}
+			// The frame is finished. Continue with the next one, if any.
+			let mut input = self.decoder.take().unwrap().into_inner();
+			if input.fill_buf()?.is_empty() {
+				return Ok(0);
+			}
+			self.decoder = Some(StreamingDecoder::new(input).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?);
+		}
+	}
+}
+
+/// Reads the first bytes of a stream without consuming them.
+fn peek_magic(mut input: Box<dyn BufRead>) -> io::Result<([u8; MAGIC_LEN], Box<dyn BufRead>)> {
+	let mut magic = [0u8; MAGIC_LEN];
+	let mut len = 0;
+	while len < MAGIC_LEN {
+		match input.read(&mut magic[len..])? {
+			0 => break,
+			n => len += n,
+		}
+	}
+	let input = Box::new(Cursor::new(magic[..len].to_vec()).chain(input));
+	Ok((magic, input))
+}
@@ -0,0 +1,271 @@
+// Powerlink Analyzer - Analyze Ethernet POWERLINK Network Traffic
+// Copyright (C) 2016, Thomas Keh
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, version 3 of the License.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The Cycle module models a POWERLINK cycle: the SoC, the isochronous PReq/PRes pairs,
+//! the SoA with the invited service and the asynchronous frame that follows it.
+
+use time::{Duration,Timespec};
+use types::*;
+use frame::Frame;
+
+/// A PReq and the PRes answering it. A PRes without PReq, like the PRes of the MN, has no
+/// request time.
+#[derive(Debug, Clone)]
+pub struct Poll {
+	pub node_id: u8,
+	pub preq_ts: Option<Timespec>,
+	pub pres_ts: Option<Timespec>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SoaFrame {
+	pub ts: Timespec,
+	pub service: String,
+	pub target: u8,
+}
+
+/// The first frame after the SoA.
+#[derive(Debug, Clone)]
+pub struct AsyncFrame {
+	pub ts: Timespec,
+	/// The ASnd service, or `Ethernet` for a non-POWERLINK frame.
+	pub kind: String,
+	pub src: Option<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Cycle {
+	/// Cycle 1 starts with the first SoC.
+	pub number: u64,
+	pub mn_state: Option<NmtState>,
+	pub soc_ts: Timespec,
+	pub polls: Vec<Poll>,
+	pub soa: Option<SoaFrame>,
+	pub async_frame: Option<AsyncFrame>,
+	/// Time of the last frame of the cycle.
+	pub last_ts: Timespec,
+	/// Time of the next SoC.
+	pub end_ts: Option<Timespec>,
+	pub frames: usize,
+	pub first_packet_id: usize,
+	pub last_packet_id: usize,
+}
+
+impl Cycle {
+
+	/// Starts a cycle with its SoC.
+	pub fn new(number: u64, mn_state: Option<NmtState>, soc_ts: Timespec, packet_id: usize) -> Self {
+		Cycle {
+			number,
+			mn_state,
+			soc_ts,
+			polls: Vec::new(),
+			soa: None,
+			async_frame: None,
+			last_ts: soc_ts,
+			end_ts: None,
+			frames: 1,
+			first_packet_id: packet_id,
+			last_packet_id: packet_id,
+		}
+	}
+
+	/// Adds a frame following the SoC. `frame` is `None` for non-POWERLINK packets.
+	pub fn add_frame(&mut self, frame: Option<&Frame>, ts: Timespec, packet_id: usize) {
+
+		self.frames += 1;
+		self.last_ts = ts;
+		self.last_packet_id = packet_id;
+
+		match frame {
+			Some(Frame::PReq(preq)) => self.polls.push(Poll { node_id: preq.dst, preq_ts: Some(ts), pres_ts: None }),
+			Some(Frame::PRes(pres)) => match self.polls.last_mut() {
+				Some(poll) if poll.node_id == pres.src && poll.pres_ts.is_none() => poll.pres_ts = Some(ts),
+				_ => self.polls.push(Poll { node_id: pres.src, preq_ts: None, pres_ts: Some(ts) }),
+			},
+			Some(Frame::SoA(soa)) => if self.soa.is_none() {
+				let service = match soa.service() {
+					Some(service) => format!("{:?}", service),
+					None => format!("0x{:02X}", soa.service_id),
+				};
+				self.soa = Some(SoaFrame { ts, service, target: soa.target });
+			},
+			Some(Frame::ASnd(asnd)) => if self.soa.is_some() && self.async_frame.is_none() {
+				let kind = match asnd.service() {
+					Some(service) => format!("{:?}", service),
+					None => format!("0x{:02X}", asnd.service_id),
+				};
+				self.async_frame = Some(AsyncFrame { ts, kind, src: Some(asnd.src) });
+			},
+			Some(Frame::SoC(_)) => {},
+			None => if self.soa.is_some() && self.async_frame.is_none() {
+				self.async_frame = Some(AsyncFrame { ts, kind: "Ethernet".to_string(), src: None });
+			},
+		}
+	}
+
+	/// Ends the cycle at the next SoC.
+	pub fn finish(&mut self, end_ts: Timespec) {
+		self.end_ts = Some(end_ts);
+	}
+
+	/// Time from this SoC to the next one.
+	pub fn duration(&self) -> Option<Duration> {
+		self.end_ts.map(|end| end - self.soc_ts)
+	}
+
+	pub fn preq_count(&self) -> usize {
+		self.polls.iter().filter(|p| p.preq_ts.is_some()).count()
+	}
+
+	pub fn pres_count(&self) -> usize {
+		self.polls.iter().filter(|p| p.pres_ts.is_some()).count()
+	}
+
+	/// Number of PReqs without PRes.
+	pub fn missing_pres(&self) -> usize {
+		self.polls.iter().filter(|p| p.preq_ts.is_some() && p.pres_ts.is_none()).count()
+	}
+
+	/// Time from the SoC to the SoA. Unlike the isochronous phase of `phases`, this includes
+	/// the time before the first PReq and after the last PRes.
+	pub fn soc_soa(&self) -> Option<Duration> {
+		self.soa.as_ref().map(|soa| soa.ts - self.soc_ts)
+	}
+
+	/// Splits the cycle into phases: SoC to the first PReq, first PReq to the last PRes, last
+	/// PRes to the SoA, SoA to the asynchronous frame and the idle time until the next SoC.
+	/// Phases whose frames are missing are left out. PReqs and PRes after the SoA do not
+	/// belong to the isochronous phase.
+	pub fn phases(&self) -> Vec<(&'static str, Duration)> {
+		let soa = self.soa.as_ref().map(|soa| soa.ts);
+		let isochronous = |ts: &Timespec| soa.is_none_or(|soa| *ts < soa);
+		let first_preq = self.polls.iter().filter_map(|p| p.preq_ts).filter(isochronous).min();
+		let last_pres = self.polls.iter().filter_map(|p| p.pres_ts).filter(isochronous).max();
+		let mut phases = Vec::new();
+		if let Some(first_preq) = first_preq {
+			phases.push(("soc_preq", first_preq - self.soc_ts));
+		}
+		if let (Some(first_preq), Some(last_pres)) = (first_preq, last_pres) {
+			phases.push(("isochronous", last_pres - first_preq));
+		}
+		if let (Some(last_pres), Some(soa)) = (last_pres, soa) {
+			phases.push(("pres_soa", soa - last_pres));
+		}
+		if let Some(asynchronous) = self.asynchronous() {
+			phases.push(("soa_async", asynchronous));
+		}
+		if let Some(end) = self.end_ts {
+			phases.push(("idle", end - self.last_ts));
+		}
+		phases
+	}
+
+	/// Time from the SoA to the asynchronous frame.
+	pub fn asynchronous(&self) -> Option<Duration> {
+		match (self.soa.as_ref(), self.async_frame.as_ref()) {
+			(Some(soa), Some(frame)) => Some(frame.ts - soa.ts),
+			_ => None,
+		}
+	}
+
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+
+	const SOA: &[u8] = &[0x05, 255, 240, 0xFD, 0, 0, 0x02, 1, 0x20];
+	const STATUS_RESPONSE: &[u8] = &[0x06, 240, 1, 0x02];
+
+	fn preq(dst: u8) -> Vec<u8> {
+		vec![0x03, dst, 240, 0, 0, 0, 0, 0, 0, 0]
+	}
+
+	fn pres(src: u8) -> Vec<u8> {
+		vec![0x04, 255, src, 0xFD, 0, 0, 0, 0, 0, 0]
+	}
+
+	fn us(us: i64) -> Timespec {
+		Timespec::new(0, 0) + Duration::microseconds(us)
+	}
+
+	/// Builds a cycle from frames given with their time in microseconds after the SoC and
+	/// ends it at `end`.
+	fn cycle(frames: &[(i64, &[u8])], end: i64) -> Cycle {
+		let mut cycle = Cycle::new(1, None, us(0), 1);
+		for (i, &(ts, data)) in frames.iter().enumerate() {
+			cycle.add_frame(Frame::parse(data).as_ref(), us(ts), i + 2);
+		}
+		cycle.finish(us(end));
+		cycle
+	}
+
+	fn phases(cycle: &Cycle) -> Vec<(&'static str, i64)> {
+		cycle.phases().into_iter().map(|(phase, d)| (phase, d.num_microseconds().unwrap())).collect()
+	}
+
+	#[test]
+	fn full_cycle() {
+		let cycle = cycle(&[
+			(10, &preq(1)), (20, &pres(1)),
+			(30, &preq(2)), (45, &pres(2)),
+			(48, &pres(240)),
+			(50, SOA), (60, STATUS_RESPONSE),
+		], 100);
+		assert_eq!(phases(&cycle), vec![("soc_preq", 10), ("isochronous", 38), ("pres_soa", 2), ("soa_async", 10), ("idle", 40)]);
+		assert_eq!((cycle.preq_count(), cycle.pres_count(), cycle.missing_pres()), (2, 3, 0));
+		assert_eq!(cycle.soc_soa(), Some(Duration::microseconds(50)));
+		assert_eq!(cycle.duration(), Some(Duration::microseconds(100)));
+		assert_eq!((cycle.frames, cycle.first_packet_id, cycle.last_packet_id), (8, 1, 8));
+		let async_frame = cycle.async_frame.unwrap();
+		assert_eq!((async_frame.kind.as_str(), async_frame.src), ("Status", Some(1)));
+	}
+
+	#[test]
+	fn cycle_without_preqs() {
+		// Only the MN sends its PRes.
+		let cycle = self::cycle(&[(5, &pres(240)), (20, SOA), (30, STATUS_RESPONSE)], 50);
+		assert_eq!(phases(&cycle), vec![("pres_soa", 15), ("soa_async", 10), ("idle", 20)]);
+
+		let cycle = self::cycle(&[(20, SOA), (30, STATUS_RESPONSE)], 50);
+		assert_eq!(phases(&cycle), vec![("soa_async", 10), ("idle", 20)]);
+		assert_eq!(cycle.preq_count(), 0);
+	}
+
+	#[test]
+	fn cycle_without_async_frame() {
+		let cycle = self::cycle(&[(10, &preq(1)), (20, &pres(1)), (30, SOA)], 80);
+		assert_eq!(phases(&cycle), vec![("soc_preq", 10), ("isochronous", 10), ("pres_soa", 10), ("idle", 50)]);
+		assert_eq!(cycle.asynchronous(), None);
+	}
+
+	#[test]
+	fn cycle_with_missing_frames() {
+		// The PRes of node 2 is missing and a PReq follows the SoA.
+		let cycle = self::cycle(&[(10, &preq(1)), (20, &pres(1)), (30, &preq(2)), (50, SOA), (55, &preq(3)), (70, &[0xFF; 20])], 90);
+		assert_eq!(phases(&cycle), vec![("soc_preq", 10), ("isochronous", 10), ("pres_soa", 30), ("soa_async", 20), ("idle", 20)]);
+		assert_eq!(cycle.missing_pres(), 2);
+		assert_eq!(cycle.async_frame.unwrap().kind, "Ethernet");
+
+		// Without the next SoC there is neither a duration nor an idle phase.
+		let mut cycle = Cycle::new(1, None, us(0), 1);
+		cycle.add_frame(Frame::parse(&preq(1)).as_ref(), us(10), 2);
+		assert_eq!(phases(&cycle), vec![("soc_preq", 10)]);
+		assert_eq!((cycle.duration(), cycle.soc_soa()), (None, None));
+	}
+
+}
@@ -15,18 +15,39 @@
 
 //! The Database module stores latencies together with some metadata.
 
-use rusqlite::Connection;
-use time::Duration;
+use rusqlite::{Connection,Row};
+use time::{self,Duration,Timespec};
 use types::*;
+use frame::Flags;
+use pdo::{PdoObject,PdoValue};
+use sdo::{SdoFrame,SdoTransfer,ObjectId};
+use nmt::NmtCommand;
+use ident::IdentResponse;
+use status::{StatusResponse,ErrorEntry};
+use rusqlite::types::ToSql;
 use std::cmp;
 use enum_primitive::FromPrimitive;
 use rusqlite::Result;
 use std::cell::Cell;
+use capture::Resolution;
+use cycle::Cycle;
+use plkan::PlkanState;
+use pcapng::{Section,Interface};
+use std::path::Path;
+
+/// Version of the table layout described in SCHEMA.md. Increase it on every change.
+pub const SCHEMA_VERSION: i64 = 6;
+
+/// Latencies whose median is less than this multiple of the timestamp resolution are flagged.
+const MIN_RESOLUTION_RATIO: f64 = 10.0;
 
 pub struct Database {
 	connection: Connection,
 	total_time: Cell<Duration>,
-	total_num_packets: Cell<usize>
+	total_num_packets: Cell<usize>,
+	resolution: Cell<Resolution>,
+	capture_id: Cell<usize>,
+	recording: Cell<bool>,
 }
 
 pub struct ResponseStats {
@@ -37,53 +58,656 @@ pub struct ResponseStats {
 	pub jitter_rel: f64,
 	pub quartile1: i64,
 	pub median: i64,
-	pub quartile3: i64
+	pub quartile3: i64,
+	/// The timestamp resolution is too coarse for meaningful latencies.
+	pub coarse: bool,
+}
+
+/// The frame a result was derived from.
+#[derive(Debug, Clone, Copy)]
+pub struct Origin {
+	pub capture_time: Timespec,
+	/// Time since the first packet.
+	pub timestamp: Duration,
+	pub cycle: u64,
+	/// The frame that started the measurement, e.g. the PReq of a PRes.
+	pub request_packet_id: Option<usize>,
+	pub packet_id: usize,
+}
+
+pub struct ResponseRow {
+	pub timediff_ns: u64,
+	pub packet_type: String,
+	pub node_id: u8,
+	pub origin: Origin,
+}
+
+/// Errors of the same type, node and states.
+pub struct ErrorGroup {
+	pub node_id: u8,
+	pub error_type: String,
+	/// Unknown if no state was known.
+	pub cn_state: NmtState,
+	pub mn_state: NmtState,
+	pub count: usize,
+	pub first_packet_id: usize,
+	pub last_packet_id: usize,
+}
+
+pub struct FileRow {
+	pub file_id: usize,
+	pub path: String,
+	pub packets: usize,
+	pub first_ts_ns: Option<i64>,
+	pub last_ts_ns: Option<i64>,
+}
+
+/// Where a followed file was left off.
+pub struct FollowState {
+	pub path: String,
+	/// Records analyzed so far.
+	pub records: usize,
+	/// File offset after the last analyzed record and the headers needed to read on from
+	/// there. Compressed files have no offset and are replayed instead.
+	pub resume: Option<(u64, Vec<u8>)>,
+	/// Records per interface.
+	pub packets: Vec<usize>,
+	pub first_ts: Option<Timespec>,
+	pub last_ts: Option<Timespec>,
+	pub plkan: PlkanState,
+}
+
+pub struct SdoTransferRow {
+	pub client: u8,
+	pub server: u8,
+	pub command: String,
+	pub object: Option<(u16,u8)>,
+	pub duration_ns: i64,
+	pub cycles: usize,
+	pub bytes: u64,
+	pub retransmissions: usize,
+	pub result: String,
+	pub timestamp: i64,
+	pub packet_id: usize,
+}
+
+pub struct NmtCommandRow {
+	pub node_id: u8,
+	pub command: String,
+	pub timestamp: i64,
+	pub packet_id: usize,
+	/// Time until the node changed its state, if it did.
+	pub delay_ns: Option<i64>,
+	pub state: Option<NmtState>,
 }
 
 impl Database {
 
 	pub fn new() -> Self {
-		let conn = Connection::open_in_memory().unwrap();
+		Database::with_connection(Connection::open_in_memory().unwrap())
+	}
+
+	/// Opens or creates a database file, so that the results outlive the program. Results
+	/// are only written to the file with `commit`.
+	pub fn open(path: &Path) -> ::std::result::Result<Self, String> {
+		let conn = Connection::open(path).map_err(|e| format!("Cannot open database {}: {}", path.display(), e))?;
+		let version: i64 = conn.query_row("PRAGMA user_version", &[], |row| row.get(0)).map_err(|e| format!("Cannot open database {}: {}", path.display(), e))?;
+		if version != 0 && version != SCHEMA_VERSION {
+			return Err(format!("Database {} has schema version {}, expected {}.", path.display(), version, SCHEMA_VERSION));
+		}
+		Ok(Database::with_connection(conn))
+	}
+
+	fn with_connection(conn: Connection) -> Self {
+
+		// All results are written in one transaction per commit.
+		conn.execute_batch("BEGIN").unwrap();
 
 		conn.execute("
-			CREATE TABLE soc (
+			CREATE TABLE IF NOT EXISTS soc (
 				id              INTEGER PRIMARY KEY,
-				timediff_ns     INTEGER NOT NULL,
-				mn_state        INTEGER
+				capture_id      INTEGER NOT NULL,
+				timediff_ns     INTEGER,
+				mn_state        INTEGER,
+				capture_time_ns INTEGER NOT NULL,
+				net_time_ns     INTEGER NOT NULL,
+				relative_time_us INTEGER NOT NULL,
+				timestamp       INTEGER NOT NULL,
+				cycle           INTEGER NOT NULL,
+				request_packet_id INTEGER,
+				packet_id       INTEGER NOT NULL
+			)", &[]).unwrap();
+
+		// Cycles from one SoC to the next. The last, incomplete cycle is not stored.
+		conn.execute("
+			CREATE TABLE IF NOT EXISTS cycle (
+				id              INTEGER PRIMARY KEY,
+				capture_id      INTEGER NOT NULL,
+				cycle           INTEGER NOT NULL,
+				mn_state        INTEGER,
+				capture_time_ns INTEGER NOT NULL,
+				timestamp       INTEGER NOT NULL,
+				duration_ns     INTEGER NOT NULL,
+				frames          INTEGER NOT NULL,
+				preq_count      INTEGER NOT NULL,
+				pres_count      INTEGER NOT NULL,
+				missing_pres    INTEGER NOT NULL,
+				soc_soa_ns      INTEGER,
+				soa_service     TEXT,
+				soa_target      INTEGER,
+				async_type      TEXT,
+				async_src       INTEGER,
+				async_ns        INTEGER,
+				first_packet_id INTEGER NOT NULL,
+				last_packet_id  INTEGER NOT NULL
+			)", &[]).unwrap();
+
+		conn.execute("
+			CREATE TABLE IF NOT EXISTS cycle_phase (
+				id              INTEGER PRIMARY KEY,
+				capture_id      INTEGER NOT NULL,
+				cycle           INTEGER NOT NULL,
+				phase           TEXT NOT NULL,
+				timediff_ns     INTEGER NOT NULL
 			)", &[]).unwrap();
 
 		conn.execute("
-			CREATE TABLE response (
+			CREATE TABLE IF NOT EXISTS response (
 				id              INTEGER PRIMARY KEY,
+				capture_id      INTEGER NOT NULL,
 				type            TEXT NOT NULL,
 				node_id         INTEGER NOT NULL,
 				timediff_ns     INTEGER NOT NULL,
 				cn_state        INTEGER,
-				mn_state        INTEGER
+				mn_state        INTEGER,
+				capture_time_ns INTEGER NOT NULL,
+				timestamp       INTEGER NOT NULL,
+				cycle           INTEGER NOT NULL,
+				request_packet_id INTEGER,
+				packet_id       INTEGER NOT NULL
 			)", &[]).unwrap();
 
 		conn.execute("
-			CREATE TABLE errors (
+			CREATE TABLE IF NOT EXISTS errors (
 				id              INTEGER PRIMARY KEY,
+				capture_id      INTEGER NOT NULL,
 				type            TEXT NOT NULL,
 				node_id         INTEGER NOT NULL,
 				cn_state        INTEGER,
-				mn_state        INTEGER
+				mn_state        INTEGER,
+				capture_time_ns INTEGER NOT NULL,
+				timestamp       INTEGER NOT NULL,
+				cycle           INTEGER NOT NULL,
+				request_packet_id INTEGER,
+				packet_id       INTEGER NOT NULL
 			)", &[]).unwrap();
 
 		conn.execute("
-			CREATE TABLE state_changes (
+			CREATE TABLE IF NOT EXISTS state_changes (
 				id              INTEGER PRIMARY KEY,
+				capture_id      INTEGER NOT NULL,
 				node_id         INTEGER NOT NULL,
 				state           INTEGER,
 				timestamp       INTEGER NOT NULL,
 				packet_id		INTEGER NOT NULL
 			)", &[]).unwrap();
 
+		conn.execute("
+			CREATE TABLE IF NOT EXISTS flags (
+				id              INTEGER PRIMARY KEY,
+				capture_id      INTEGER NOT NULL,
+				type            TEXT NOT NULL,
+				node_id         INTEGER NOT NULL,
+				mc              INTEGER NOT NULL,
+				ps              INTEGER NOT NULL,
+				ms              INTEGER NOT NULL,
+				ea              INTEGER NOT NULL,
+				er              INTEGER NOT NULL,
+				en              INTEGER NOT NULL,
+				rd              INTEGER NOT NULL,
+				pr              INTEGER NOT NULL,
+				rs              INTEGER NOT NULL,
+				cn_state        INTEGER,
+				mn_state        INTEGER,
+				timestamp       INTEGER NOT NULL,
+				packet_id       INTEGER NOT NULL
+			)", &[]).unwrap();
+
+		conn.execute("
+			CREATE TABLE IF NOT EXISTS exception_handshake (
+				id              INTEGER PRIMARY KEY,
+				capture_id      INTEGER NOT NULL,
+				node_id         INTEGER NOT NULL,
+				timediff_ns     INTEGER NOT NULL,
+				timestamp       INTEGER NOT NULL
+			)", &[]).unwrap();
+
+		conn.execute("
+			CREATE TABLE IF NOT EXISTS pdo_values (
+				id              INTEGER PRIMARY KEY,
+				capture_id      INTEGER NOT NULL,
+				cycle           INTEGER NOT NULL,
+				timestamp       INTEGER NOT NULL,
+				node_id         INTEGER NOT NULL,
+				type            TEXT NOT NULL,
+				object_index    INTEGER NOT NULL,
+				object_subindex INTEGER NOT NULL,
+				value           NUMERIC NOT NULL
+			)", &[]).unwrap();
+
+		conn.execute("
+			CREATE TABLE IF NOT EXISTS sdo (
+				id              INTEGER PRIMARY KEY,
+				capture_id      INTEGER NOT NULL,
+				src             INTEGER NOT NULL,
+				dst             INTEGER NOT NULL,
+				receive_seq     INTEGER NOT NULL,
+				receive_con     TEXT NOT NULL,
+				send_seq        INTEGER NOT NULL,
+				send_con        TEXT NOT NULL,
+				transaction_id  INTEGER,
+				command         TEXT,
+				response        INTEGER,
+				abort           INTEGER,
+				segmentation    TEXT,
+				data_size       INTEGER,
+				object_index    INTEGER,
+				object_subindex INTEGER,
+				result          TEXT,
+				abort_code      INTEGER,
+				timestamp       INTEGER NOT NULL,
+				packet_id       INTEGER NOT NULL
+			)", &[]).unwrap();
+
+		conn.execute("
+			CREATE TABLE IF NOT EXISTS sdo_transfer (
+				id              INTEGER PRIMARY KEY,
+				capture_id      INTEGER NOT NULL,
+				client          INTEGER NOT NULL,
+				node_id         INTEGER NOT NULL,
+				transaction_id  INTEGER NOT NULL,
+				command         TEXT NOT NULL,
+				object_index    INTEGER,
+				object_subindex INTEGER,
+				timediff_ns     INTEGER NOT NULL,
+				cycles          INTEGER NOT NULL,
+				frames          INTEGER NOT NULL,
+				bytes           INTEGER NOT NULL,
+				data_size       INTEGER,
+				retransmissions INTEGER NOT NULL,
+				result          TEXT NOT NULL,
+				abort_code      INTEGER,
+				timestamp       INTEGER NOT NULL,
+				packet_id       INTEGER NOT NULL
+			)", &[]).unwrap();
+
+		conn.execute("
+			CREATE TABLE IF NOT EXISTS nmt_commands (
+				id              INTEGER PRIMARY KEY,
+				capture_id      INTEGER NOT NULL,
+				command         TEXT NOT NULL,
+				node_id         INTEGER NOT NULL,
+				timestamp       INTEGER NOT NULL,
+				packet_id       INTEGER NOT NULL,
+				timediff_ns     INTEGER,
+				state           INTEGER,
+				state_packet_id INTEGER
+			)", &[]).unwrap();
+
+		conn.execute("
+			CREATE TABLE IF NOT EXISTS ident (
+				id              INTEGER PRIMARY KEY,
+				capture_id      INTEGER NOT NULL,
+				node_id         INTEGER NOT NULL,
+				nmt_state       INTEGER,
+				epl_version     INTEGER NOT NULL,
+				feature_flags   INTEGER NOT NULL,
+				mtu             INTEGER NOT NULL,
+				poll_in_size    INTEGER NOT NULL,
+				poll_out_size   INTEGER NOT NULL,
+				response_time   INTEGER NOT NULL,
+				device_type     INTEGER NOT NULL,
+				vendor_id       INTEGER NOT NULL,
+				product_code    INTEGER NOT NULL,
+				revision_number INTEGER NOT NULL,
+				serial_number   INTEGER NOT NULL,
+				verify_conf_date INTEGER NOT NULL,
+				verify_conf_time INTEGER NOT NULL,
+				ip_address      BLOB NOT NULL,
+				subnet_mask     BLOB NOT NULL,
+				default_gateway BLOB NOT NULL,
+				host_name       TEXT NOT NULL,
+				timestamp       INTEGER NOT NULL,
+				packet_id       INTEGER NOT NULL,
+				UNIQUE (capture_id, node_id)
+			)", &[]).unwrap();
+
+		conn.execute("
+			CREATE TABLE IF NOT EXISTS static_errors (
+				id              INTEGER PRIMARY KEY,
+				capture_id      INTEGER NOT NULL,
+				node_id         INTEGER NOT NULL,
+				error_register  INTEGER NOT NULL,
+				static_errors   BLOB NOT NULL,
+				en              INTEGER NOT NULL,
+				ec              INTEGER NOT NULL,
+				pr              INTEGER NOT NULL,
+				rs              INTEGER NOT NULL,
+				nmt_state       INTEGER,
+				timestamp       INTEGER NOT NULL,
+				packet_id       INTEGER NOT NULL
+			)", &[]).unwrap();
+
+		conn.execute("
+			CREATE TABLE IF NOT EXISTS error_entries (
+				id              INTEGER PRIMARY KEY,
+				capture_id      INTEGER NOT NULL,
+				node_id         INTEGER NOT NULL,
+				entry_type      INTEGER NOT NULL,
+				error_code      INTEGER NOT NULL,
+				net_time_ns     INTEGER NOT NULL,
+				additional_info INTEGER NOT NULL,
+				timestamp       INTEGER NOT NULL,
+				packet_id       INTEGER NOT NULL,
+				UNIQUE (capture_id, node_id, entry_type, error_code, net_time_ns, additional_info)
+			)", &[]).unwrap();
+
+		conn.execute("
+			CREATE TABLE IF NOT EXISTS vlan (
+				id              INTEGER PRIMARY KEY,
+				capture_id      INTEGER NOT NULL,
+				vlan_id         INTEGER NOT NULL,
+				outer_vlan_id   INTEGER,
+				packet_id       INTEGER NOT NULL
+			)", &[]).unwrap();
+
+		conn.execute("
+			CREATE TABLE IF NOT EXISTS files (
+				id              INTEGER PRIMARY KEY,
+				capture_id      INTEGER NOT NULL,
+				file_id         INTEGER NOT NULL,
+				path            TEXT NOT NULL,
+				packets         INTEGER NOT NULL,
+				first_ts_ns     INTEGER,
+				last_ts_ns      INTEGER
+			)", &[]).unwrap();
+
+		// Packets from first_packet_id up to the next run belong to file_id.
+		conn.execute("
+			CREATE TABLE IF NOT EXISTS file_runs (
+				id              INTEGER PRIMARY KEY,
+				capture_id      INTEGER NOT NULL,
+				first_packet_id INTEGER NOT NULL,
+				file_id         INTEGER NOT NULL
+			)", &[]).unwrap();
+
+		conn.execute("CREATE INDEX IF NOT EXISTS file_runs_packet ON file_runs (capture_id, first_packet_id)", &[]).unwrap();
+
+		conn.execute("
+			CREATE TABLE IF NOT EXISTS sections (
+				id              INTEGER PRIMARY KEY,
+				capture_id      INTEGER NOT NULL,
+				file_id         INTEGER NOT NULL,
+				hardware        TEXT,
+				os              TEXT,
+				application     TEXT,
+				comment         TEXT
+			)", &[]).unwrap();
+
+		conn.execute("
+			CREATE TABLE IF NOT EXISTS interfaces (
+				id              INTEGER PRIMARY KEY,
+				capture_id      INTEGER NOT NULL,
+				file_id         INTEGER NOT NULL,
+				interface_id    INTEGER NOT NULL,
+				link_type       INTEGER NOT NULL,
+				name            TEXT,
+				description     TEXT,
+				tsresol         INTEGER NOT NULL,
+				tsoffset        INTEGER NOT NULL,
+				packets         INTEGER NOT NULL
+			)", &[]).unwrap();
+
+		conn.execute("
+			CREATE TABLE IF NOT EXISTS comments (
+				id              INTEGER PRIMARY KEY,
+				capture_id      INTEGER NOT NULL,
+				packet_id       INTEGER NOT NULL,
+				file_id         INTEGER NOT NULL,
+				interface_id    INTEGER NOT NULL,
+				comment         TEXT NOT NULL
+			)", &[]).unwrap();
+
+		// Several captures are only stored in one database for the aggregate report.
+		conn.execute("
+			CREATE TABLE IF NOT EXISTS captures (
+				capture_id      INTEGER PRIMARY KEY,
+				name            TEXT NOT NULL,
+				packets         INTEGER,
+				duration_ns     INTEGER,
+				tsresol         INTEGER
+			)", &[]).unwrap();
+
+		// Where the followed file was left off, see FollowState.
+		conn.execute("
+			CREATE TABLE IF NOT EXISTS follow_state (
+				path            TEXT NOT NULL,
+				records         INTEGER NOT NULL,
+				offset          INTEGER,
+				headers         BLOB,
+				first_ts_ns     INTEGER,
+				last_ts_ns      INTEGER,
+				packet_id       INTEGER NOT NULL,
+				cycle           INTEGER NOT NULL,
+				soc_ts_ns       INTEGER,
+				soc_packet_id   INTEGER,
+				mn_state        INTEGER,
+				request_type    INTEGER,
+				request_service INTEGER,
+				requested_node  INTEGER,
+				request_ts_ns   INTEGER,
+				request_packet_id INTEGER,
+				request_cycle   INTEGER NOT NULL,
+				anchor_ts_ns    INTEGER,
+				anchor_cycle    INTEGER
+			)", &[]).unwrap();
+
+		conn.execute("
+			CREATE TABLE IF NOT EXISTS follow_nodes (
+				node_id         INTEGER PRIMARY KEY,
+				state           INTEGER NOT NULL
+			)", &[]).unwrap();
+
+		conn.execute("
+			CREATE TABLE IF NOT EXISTS follow_interfaces (
+				interface_id    INTEGER PRIMARY KEY,
+				packets         INTEGER NOT NULL
+			)", &[]).unwrap();
+
+		conn.execute("
+			CREATE TABLE IF NOT EXISTS metadata (
+				capture_id      INTEGER PRIMARY KEY,
+				source          TEXT NOT NULL,
+				capture_start_ns INTEGER,
+				analyzer_version TEXT NOT NULL,
+				schema_version  INTEGER NOT NULL,
+				created_ns      INTEGER NOT NULL
+			)", &[]).unwrap();
+
+		conn.execute("
+			CREATE TABLE IF NOT EXISTS nmt_states (
+				state           INTEGER PRIMARY KEY,
+				name            TEXT NOT NULL
+			)", &[]).unwrap();
+
+		for state in 0..256 {
+			if let Some(nmt_state) = NmtState::from_i64(state) {
+				conn.execute("INSERT OR IGNORE INTO nmt_states (state, name) VALUES ($1, $2)",
+					&[&state, &format!("{:?}", nmt_state)]).unwrap();
+			}
+		}
+
+		conn.execute_batch("
+			CREATE VIEW IF NOT EXISTS response_summary AS
+				SELECT capture_id, type, node_id, COUNT(*) AS count,
+				       MIN(timediff_ns) AS min_ns, AVG(timediff_ns) AS avg_ns, MAX(timediff_ns) AS max_ns
+				FROM response
+				GROUP BY capture_id, type, node_id;
+
+			CREATE VIEW IF NOT EXISTS error_summary AS
+				SELECT capture_id, type, node_id, COUNT(*) AS count
+				FROM errors
+				GROUP BY capture_id, type, node_id;
+
+			CREATE VIEW IF NOT EXISTS state_change_log AS
+				SELECT s.capture_id, s.node_id, n.name AS state, s.timestamp / 1e9 AS seconds, s.packet_id
+				FROM state_changes s LEFT JOIN nmt_states n ON n.state = s.state
+				ORDER BY s.capture_id, s.id;
+
+			CREATE VIEW IF NOT EXISTS cycle_errors AS
+				SELECT c.capture_id, c.cycle, c.first_packet_id, c.last_packet_id, c.missing_pres, COUNT(e.id) AS errors
+				FROM cycle c LEFT JOIN errors e ON e.capture_id = c.capture_id AND e.cycle = c.cycle
+				GROUP BY c.id;
+
+			CREATE VIEW IF NOT EXISTS cycle_times AS
+				SELECT s.capture_id, s.id, s.timediff_ns / 1e3 AS cycle_time_us, n.name AS mn_state, s.capture_time_ns
+				FROM soc s LEFT JOIN nmt_states n ON n.state = s.mn_state
+				WHERE s.timediff_ns IS NOT NULL
+				ORDER BY s.capture_id, s.id;
+		").unwrap();
+
+		conn.execute_batch(&format!("PRAGMA user_version = {}", SCHEMA_VERSION)).unwrap();
+
 		return Database {
 			connection: conn,
 			total_time: Cell::new(Duration::zero()),
-			total_num_packets: Cell::new(0)
+			total_num_packets: Cell::new(0),
+			resolution: Cell::new(Resolution::default()),
+			capture_id: Cell::new(0),
+			recording: Cell::new(true),
+		}
+	}
+
+	/// Writes all results so far to the database file and starts a new transaction.
+	pub fn commit(&self) {
+		self.connection.execute_batch("COMMIT; BEGIN").unwrap();
+	}
+
+	/// Stores where the current capture comes from and which analyzer created the results.
+	pub fn insert_metadata(&self, source: &str, capture_start: Option<Timespec>) {
+		self.connection.execute("
+			INSERT OR REPLACE INTO metadata (capture_id, source, capture_start_ns, analyzer_version, schema_version, created_ns)
+			VALUES ($1, $2, $3, $4, $5, $6)",
+		&[&self.capture_id(), &source, &capture_start.map(timespec_to_ns), &env!("CARGO_PKG_VERSION"), &SCHEMA_VERSION,
+		  &timespec_to_ns(time::get_time())]).unwrap();
+	}
+
+	/// While not recording, packets are counted but no results are stored.
+	pub fn set_recording(&self, recording: bool) {
+		self.recording.set(recording);
+	}
+
+	/// Returns where the followed file was left off, if it was followed before.
+	pub fn get_follow_state(&self) -> Option<FollowState> {
+		let mut state = self.connection.query_row("
+			SELECT path, records, offset, headers, first_ts_ns, last_ts_ns, packet_id, cycle, soc_ts_ns, soc_packet_id, mn_state,
+			       request_type, request_service, requested_node, request_ts_ns, request_packet_id, request_cycle, anchor_ts_ns, anchor_cycle
+			FROM follow_state",
+		&[], |row| {
+			let offset: Option<i64> = row.get(2);
+			let headers: Option<Vec<u8>> = row.get(3);
+			let anchor_ts: Option<i64> = row.get(17);
+			let anchor_cycle: Option<i64> = row.get(18);
+			FollowState {
+				path: row.get(0),
+				records: row.get::<i32, i64>(1) as usize,
+				resume: offset.map(|offset| (offset as u64, headers.unwrap_or_default())),
+				packets: Vec::new(),
+				first_ts: row.get::<i32, Option<i64>>(4).map(ns_to_timespec),
+				last_ts: row.get::<i32, Option<i64>>(5).map(ns_to_timespec),
+				plkan: PlkanState {
+					packet_id: row.get::<i32, i64>(6) as usize,
+					cycle: row.get::<i32, i64>(7) as u64,
+					first_ts: row.get::<i32, Option<i64>>(4).map(ns_to_timespec),
+					soc_ts: row.get::<i32, Option<i64>>(8).map(ns_to_timespec),
+					soc_packet_id: row.get::<i32, Option<i64>>(9).map(|id| id as usize),
+					mn_state: row.get::<i32, Option<i64>>(10).and_then(NmtState::from_i64),
+					cn_states: Vec::new(),
+					request_type: row.get::<i32, Option<i64>>(11).and_then(PacketType::from_i64),
+					request_service: row.get::<i32, Option<i64>>(12).and_then(ServiceId::from_i64),
+					requested_node: row.get::<i32, Option<i64>>(13).map(|node| node as u8),
+					request_ts: row.get::<i32, Option<i64>>(14).map(ns_to_timespec),
+					request_packet_id: row.get::<i32, Option<i64>>(15).map(|id| id as usize),
+					request_cycle: row.get::<i32, i64>(16) as u64,
+					anchor: match (anchor_ts, anchor_cycle) {
+						(Some(ts), Some(cycle)) => Some((ns_to_timespec(ts), cycle as u64)),
+						_ => None,
+					},
+				},
+			}
+		}).ok()?;
+
+		let mut stmt = self.connection.prepare("SELECT node_id, state FROM follow_nodes ORDER BY node_id").unwrap();
+		let row_iter = stmt.query_map(&[], |row| -> (u8,Option<NmtState>) {
+			(row.get::<i32, i64>(0) as u8, NmtState::from_i64(row.get(1)))
+		}).unwrap();
+		for (node, cn_state) in row_iter.flatten() {
+			if let Some(cn_state) = cn_state {
+				state.plkan.cn_states.push((node, cn_state));
+			}
+		}
+
+		let mut stmt = self.connection.prepare("SELECT interface_id, packets FROM follow_interfaces ORDER BY interface_id").unwrap();
+		let row_iter = stmt.query_map(&[], |row| -> (usize,usize) {
+			(row.get::<i32, i64>(0) as usize, row.get::<i32, i64>(1) as usize)
+		}).unwrap();
+		for (interface_id, packets) in row_iter.flatten() {
+			if state.packets.len() <= interface_id {
+				state.packets.resize(interface_id + 1, 0);
+			}
+			state.packets[interface_id] = packets;
+		}
+
+		Some(state)
+	}
+
+	pub fn set_follow_state(&self, state: &FollowState) {
+		for table in &["follow_state", "follow_nodes", "follow_interfaces"] {
+			self.connection.execute(&format!("DELETE FROM {}", table), &[]).unwrap();
+		}
+		let plkan = &state.plkan;
+		self.connection.execute("
+			INSERT INTO follow_state (path, records, offset, headers, first_ts_ns, last_ts_ns, packet_id, cycle, soc_ts_ns, soc_packet_id,
+			                          mn_state, request_type, request_service, requested_node, request_ts_ns, request_packet_id,
+			                          request_cycle, anchor_ts_ns, anchor_cycle)
+			VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19)",
+		&[&state.path, &(state.records as i64), &state.resume.as_ref().map(|r| r.0 as i64), &state.resume.as_ref().map(|r| r.1.clone()),
+		  &state.first_ts.map(timespec_to_ns), &state.last_ts.map(timespec_to_ns), &(plkan.packet_id as i64), &(plkan.cycle as i64),
+		  &plkan.soc_ts.map(timespec_to_ns), &plkan.soc_packet_id.map(|id| id as i64), &plkan.mn_state.map(|s| s as i64),
+		  &plkan.request_type.map(|t| t as i64), &plkan.request_service.map(|s| s as i64), &plkan.requested_node.map(|n| n as i64),
+		  &plkan.request_ts.map(timespec_to_ns), &plkan.request_packet_id.map(|id| id as i64), &(plkan.request_cycle as i64),
+		  &plkan.anchor.map(|a| timespec_to_ns(a.0)), &plkan.anchor.map(|a| a.1 as i64)]).unwrap();
+		for &(node, cn_state) in &plkan.cn_states {
+			self.connection.execute("INSERT INTO follow_nodes (node_id, state) VALUES ($1, $2)",
+			&[&(node as i64), &(cn_state as i64)]).unwrap();
+		}
+		for (interface_id, &packets) in state.packets.iter().enumerate() {
+			self.connection.execute("INSERT INTO follow_interfaces (interface_id, packets) VALUES ($1, $2)",
+			&[&(interface_id as i64), &(packets as i64)]).unwrap();
+		}
+	}
+
+	/// Restores the packet counters of a followed file that is continued.
+	pub fn restore_counters(&self, num_packets: usize, total_time: Duration) {
+		self.total_num_packets.set(num_packets);
+		self.total_time.set(total_time);
+	}
+
+	/// Removes the file, section and interface rows of the current capture before they are
+	/// stored again with updated counts.
+	pub fn clear_file_info(&self) {
+		for table in &["files", "sections", "interfaces"] {
+			self.connection.execute(&format!("DELETE FROM {} WHERE capture_id=$1", table), &[&self.capture_id()]).unwrap();
 		}
 	}
 
@@ -100,21 +724,106 @@ impl Database {
 		self.total_num_packets.get()
 	}
 
-	pub fn insert_soc(&self, timediff: Duration, mn_state: Option<NmtState>) {
+	/// Stores all following results under a new capture and resets the per-capture
+	/// counters. Returns the capture ID.
+	pub fn begin_capture(&self, name: &str) -> usize {
+		let capture_id: i64 = self.connection.query_row("SELECT COUNT(*) FROM captures", &[], |row| row.get(0)).unwrap();
+		self.connection.execute("
+			INSERT INTO captures (capture_id, name)
+			VALUES ($1, $2)",
+		&[&capture_id, &name]).unwrap();
+		self.capture_id.set(capture_id as usize);
+		self.total_time.set(Duration::zero());
+		self.total_num_packets.set(0);
+		self.resolution.set(Resolution::default());
+		capture_id as usize
+	}
+
+	/// Stores the counters of the current capture.
+	pub fn end_capture(&self) {
+		self.connection.execute("
+			UPDATE captures SET packets=$1, duration_ns=$2, tsresol=$3
+			WHERE capture_id=$4",
+		&[&(self.get_total_num_packets() as i64), &self.get_total_time().num_nanoseconds().unwrap(),
+		  &(self.get_resolution().tsresol() as i64), &self.capture_id()]).unwrap();
+	}
+
+	/// Returns ID, name, number of packets, duration and timestamp resolution of all captures.
+	pub fn get_captures(&self) -> Vec<(usize,String,usize,i64,Resolution)> {
+		let mut result = Vec::new();
+		let mut stmt = self.connection.prepare("SELECT capture_id, name, packets, duration_ns, tsresol FROM captures ORDER BY capture_id").unwrap();
+		let row_iter = stmt.query_map(&[], |row| -> (usize,String,usize,i64,Resolution) {
+			(row.get::<i32, i64>(0) as usize, row.get(1), row.get::<i32, i64>(2) as usize, row.get(3),
+			 Resolution::from_tsresol(row.get::<i32, i64>(4) as u8))
+		}).unwrap();
+		for row in row_iter.flatten() {
+			result.push(row);
+		}
+		result
+	}
+
+	fn capture_id(&self) -> i64 {
+		self.capture_id.get() as i64
+	}
+
+	pub fn set_resolution(&self, resolution: Resolution) {
+		self.resolution.set(resolution);
+	}
+
+	pub fn get_resolution(&self) -> Resolution {
+		self.resolution.get()
+	}
+
+	/// Stores a cycle time. The request of `origin` is the previous SoC. The first SoC of a
+	/// capture has no `timediff`.
+	pub fn insert_soc(&self, timediff: Option<Duration>, mn_state: Option<NmtState>, origin: &Origin, net_time: Timespec, relative_time_us: u64) {
+		if !self.recording.get() {
+			return;
+		}
 		trace!("Insert SoC");
-		let ns = timediff.num_nanoseconds().expect("Timediff is too large to represent it as nanoseconds. Timediffs this lare probably mean an error.");
+		let ns = timediff.map(|timediff| timediff.num_nanoseconds().expect("Timediff is too large to represent it as nanoseconds. Timediffs this lare probably mean an error."));
 		let state = match mn_state {
 			Some(s) => Some((s as u8) as i64),
 			None => None
 		};
 
 		self.connection.execute("
-			INSERT INTO soc (timediff_ns, mn_state)
-			VALUES ($1, $2)",
-		&[&(ns as i64), &state]).unwrap();
+			INSERT INTO soc (timediff_ns, mn_state, capture_time_ns, net_time_ns, relative_time_us, timestamp, cycle, request_packet_id, packet_id, capture_id)
+			VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+		&[&ns, &state, &timespec_to_ns(origin.capture_time), &timespec_to_ns(net_time), &(relative_time_us as i64),
+		  &origin.timestamp.num_nanoseconds().unwrap(), &(origin.cycle as i64), &origin.request_packet_id.map(|id| id as i64),
+		  &(origin.packet_id as i64), &self.capture_id()]).unwrap();
+	}
+
+	/// Stores a finished cycle that started at `timestamp` together with its phases. Unlike other results, cycles are
+	/// stored regardless of `set_recording`, because a cycle is only finished with the next
+	/// SoC. The caller decides whether the cycle is within the analysis window.
+	pub fn insert_cycle(&self, cycle: &Cycle, timestamp: Duration) {
+		let ns = |d: Duration| d.num_nanoseconds().unwrap();
+		self.connection.execute("
+			INSERT INTO cycle (cycle, mn_state, capture_time_ns, timestamp, duration_ns, frames, preq_count, pres_count, missing_pres,
+			                   soc_soa_ns, soa_service, soa_target, async_type, async_src, async_ns, first_packet_id, last_packet_id, capture_id)
+			VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18)",
+		&[&(cycle.number as i64), &cycle.mn_state.map(|s| (s as u8) as i64), &timespec_to_ns(cycle.soc_ts), &ns(timestamp),
+		  &cycle.duration().map(ns).unwrap_or(0), &(cycle.frames as i64),
+		  &(cycle.preq_count() as i64), &(cycle.pres_count() as i64), &(cycle.missing_pres() as i64),
+		  &cycle.soc_soa().map(ns), &cycle.soa.as_ref().map(|soa| soa.service.clone()), &cycle.soa.as_ref().map(|soa| soa.target as i64),
+		  &cycle.async_frame.as_ref().map(|frame| frame.kind.clone()), &cycle.async_frame.as_ref().and_then(|frame| frame.src).map(|src| src as i64),
+		  &cycle.asynchronous().map(ns), &(cycle.first_packet_id as i64), &(cycle.last_packet_id as i64), &self.capture_id()]).unwrap();
+
+		for (phase, duration) in cycle.phases() {
+			self.connection.execute("
+				INSERT INTO cycle_phase (cycle, phase, timediff_ns, capture_id)
+				VALUES ($1, $2, $3, $4)",
+			&[&(cycle.number as i64), &phase, &ns(duration), &self.capture_id()]).unwrap();
+		}
 	}
 
-	pub fn insert_response(&self, packet_type: &str, node_id: u8, timediff: Duration, mn_state: Option<NmtState>, cn_state: Option<NmtState>) {
+	#[allow(clippy::too_many_arguments)]
+	pub fn insert_response(&self, packet_type: &str, node_id: u8, timediff: Duration, origin: &Origin, mn_state: Option<NmtState>, cn_state: Option<NmtState>) {
+		if !self.recording.get() {
+			return;
+		}
 
 		trace!("Insert PREs");
 		let ns = timediff.num_nanoseconds().expect("Timediff is too large to represent it as nanoseconds. Timediffs this lare probably mean an error.");
@@ -130,13 +839,18 @@ impl Database {
 		};
 
 		self.connection.execute("
-			INSERT INTO response (type, node_id, timediff_ns, cn_state, mn_state)
-			VALUES ($1, $2, $3, $4, $5)",
-		&[&packet_type, &(node_id as i64), &(ns as i64), &cn_state_u8, &mn_state_u8]).unwrap();
+			INSERT INTO response (type, node_id, timediff_ns, cn_state, mn_state, capture_time_ns, timestamp, cycle, request_packet_id, packet_id, capture_id)
+			VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
+		&[&packet_type, &(node_id as i64), &(ns as i64), &cn_state_u8, &mn_state_u8,
+		  &timespec_to_ns(origin.capture_time), &origin.timestamp.num_nanoseconds().unwrap(), &(origin.cycle as i64),
+		  &origin.request_packet_id.map(|id| id as i64), &(origin.packet_id as i64), &self.capture_id()]).unwrap();
 
 	}
 
-	pub fn insert_error(&self, packet_type: &str, node_id: u8, mn_state: Option<NmtState>, cn_state: Option<NmtState>) {
+	pub fn insert_error(&self, packet_type: &str, node_id: u8, origin: &Origin, mn_state: Option<NmtState>, cn_state: Option<NmtState>) {
+		if !self.recording.get() {
+			return;
+		}
 		
 		let cn_state_u8 = match cn_state {
 			Some(s) => Some((s as u8) as i64),
@@ -149,13 +863,18 @@ impl Database {
 		};
 		
 		self.connection.execute("
-			INSERT INTO errors (type, node_id, cn_state, mn_state)
-			VALUES ($1, $2, $3, $4)",
-		&[&packet_type, &(node_id as i64), &cn_state_u8, &mn_state_u8]).unwrap();
+			INSERT INTO errors (type, node_id, cn_state, mn_state, capture_time_ns, timestamp, cycle, request_packet_id, packet_id, capture_id)
+			VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+		&[&packet_type, &(node_id as i64), &cn_state_u8, &mn_state_u8,
+		  &timespec_to_ns(origin.capture_time), &origin.timestamp.num_nanoseconds().unwrap(), &(origin.cycle as i64),
+		  &origin.request_packet_id.map(|id| id as i64), &(origin.packet_id as i64), &self.capture_id()]).unwrap();
 
 	}
 
 	pub fn insert_state_change(&self, node_id: u8, state: Option<NmtState>, timestamp: Duration, packet_id: usize) {
+		if !self.recording.get() {
+			return;
+		}
 		
 		let state_i64 = match state {
 			Some(s) => Some((s as u8) as i64),
@@ -163,16 +882,258 @@ impl Database {
 		};
 
 		self.connection.execute("
-			INSERT INTO state_changes (node_id, state, timestamp, packet_id)
+			INSERT INTO state_changes (node_id, state, timestamp, packet_id, capture_id)
+			VALUES ($1, $2, $3, $4, $5)",
+		&[&(node_id as i64), &state_i64, &timestamp.num_nanoseconds().unwrap(), &(packet_id as i64), &self.capture_id()]).unwrap();
+
+	}
+
+	#[allow(clippy::too_many_arguments)]
+	pub fn insert_flags(&self, packet_type: PacketType, node_id: u8, flags: &Flags, timestamp: Duration, packet_id: usize, mn_state: Option<NmtState>, cn_state: Option<NmtState>) {
+		if !self.recording.get() {
+			return;
+		}
+
+		let cn_state_u8 = cn_state.map(|s| (s as u8) as i64);
+		let mn_state_u8 = mn_state.map(|s| (s as u8) as i64);
+		let type_str = format!("{:?}", packet_type).to_lowercase();
+
+		self.connection.execute("
+			INSERT INTO flags (type, node_id, mc, ps, ms, ea, er, en, rd, pr, rs, cn_state, mn_state, timestamp, packet_id, capture_id)
+			VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)",
+		&[&type_str, &(node_id as i64), &flags.mc, &flags.ps, &flags.ms, &flags.ea, &flags.er, &flags.en, &flags.rd,
+		  &(flags.pr as i64), &(flags.rs as i64), &cn_state_u8, &mn_state_u8, &timestamp.num_nanoseconds().unwrap(), &(packet_id as i64), &self.capture_id()]).unwrap();
+
+	}
+
+	pub fn insert_exception_handshake(&self, node_id: u8, timediff: Duration, timestamp: Duration) {
+		if !self.recording.get() {
+			return;
+		}
+		self.connection.execute("
+			INSERT INTO exception_handshake (node_id, timediff_ns, timestamp, capture_id)
+			VALUES ($1, $2, $3, $4)",
+		&[&(node_id as i64), &timediff.num_nanoseconds().unwrap(), &timestamp.num_nanoseconds().unwrap(), &self.capture_id()]).unwrap();
+	}
+
+	pub fn insert_pdo_value(&self, cycle: u64, timestamp: Duration, object: &PdoObject, value: PdoValue) {
+		if !self.recording.get() {
+			return;
+		}
+
+		let type_str = format!("{:?}", object.direction).to_lowercase();
+		let value: &dyn ToSql = match value {
+			PdoValue::Int(ref v) => v,
+			PdoValue::Float(ref v) => v,
+		};
+
+		self.connection.execute("
+			INSERT INTO pdo_values (cycle, timestamp, node_id, type, object_index, object_subindex, value, capture_id)
+			VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+		&[&(cycle as i64), &timestamp.num_nanoseconds().unwrap(), &(object.node_id as i64), &type_str,
+		  &(object.index as i64), &(object.subindex as i64), value, &self.capture_id()]).unwrap();
+
+	}
+
+	/// Stores a decoded SDO frame. `object` is the index and subindex the transfer refers to,
+	/// if it is known.
+	pub fn insert_sdo(&self, src: u8, dst: u8, sdo: &SdoFrame, object: Option<(u16,u8)>, timestamp: Duration, packet_id: usize) {
+		if !self.recording.get() {
+			return;
+		}
+
+		let seq = &sdo.sequence;
+		let cmd = sdo.command.as_ref();
+
+		let command = cmd.map(|c| match c.command() {
+			Some(command) => format!("{:?}", command),
+			None => format!("0x{:02X}", c.command_id),
+		});
+		let result = cmd.and_then(|c| if c.abort {
+			Some("abort")
+		} else if c.response {
+			Some("ok")
+		} else {
+			None
+		});
+
+		self.connection.execute("
+			INSERT INTO sdo (src, dst, receive_seq, receive_con, send_seq, send_con, transaction_id, command, response, abort,
+			                 segmentation, data_size, object_index, object_subindex, result, abort_code, timestamp, packet_id, capture_id)
+			VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19)",
+		&[&(src as i64), &(dst as i64),
+		  &(seq.receive_seq as i64), &format!("{:?}", seq.receive_con), &(seq.send_seq as i64), &format!("{:?}", seq.send_con),
+		  &cmd.map(|c| c.transaction_id as i64), &command, &cmd.map(|c| c.response), &cmd.map(|c| c.abort),
+		  &cmd.map(|c| format!("{:?}", c.segmentation)), &cmd.map(|c| c.object_data_size() as i64),
+		  &object.map(|o| o.0 as i64), &object.map(|o| o.1 as i64), &result, &cmd.and_then(|c| c.abort_code()).map(|c| c as i64),
+		  &timestamp.num_nanoseconds().unwrap(), &(packet_id as i64), &self.capture_id()]).unwrap();
+
+	}
+
+	pub fn insert_sdo_transfer(&self, transfer: &SdoTransfer, timestamp: Duration) {
+		if !self.recording.get() {
+			return;
+		}
+
+		let command = match transfer.command() {
+			Some(command) => format!("{:?}", command),
+			None => format!("0x{:02X}", transfer.command_id),
+		};
+
+		self.connection.execute("
+			INSERT INTO sdo_transfer (client, node_id, transaction_id, command, object_index, object_subindex, timediff_ns,
+			                          cycles, frames, bytes, data_size, retransmissions, result, abort_code, timestamp, packet_id, capture_id)
+			VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17)",
+		&[&(transfer.client as i64), &(transfer.server as i64), &(transfer.transaction_id as i64), &command,
+		  &transfer.object.map(|o| o.0 as i64), &transfer.object.map(|o| o.1 as i64),
+		  &(transfer.end-transfer.start).num_nanoseconds().unwrap(),
+		  &(transfer.cycles as i64), &(transfer.frames as i64), &(transfer.bytes as i64), &transfer.data_size.map(|s| s as i64),
+		  &(transfer.retransmissions as i64), &format!("{:?}", transfer.result).to_lowercase(), &transfer.abort_code.map(|c| c as i64),
+		  &timestamp.num_nanoseconds().unwrap(), &(transfer.start_packet_id as i64), &self.capture_id()]).unwrap();
+
+	}
+
+	/// Stores an NMT command addressed to `node_id` and returns its row ID, or `None` while
+	/// not recording.
+	pub fn insert_nmt_command(&self, command: &NmtCommand, node_id: u8, timestamp: Duration, packet_id: usize) -> Option<i64> {
+		if !self.recording.get() {
+			return None;
+		}
+
+		let command_str = match command.command() {
+			Some(id) => format!("{:?}", id),
+			None => format!("0x{:02X}", command.command_id),
+		};
+
+		self.connection.execute("
+			INSERT INTO nmt_commands (command, node_id, timestamp, packet_id, capture_id)
+			VALUES ($1, $2, $3, $4, $5)",
+		&[&command_str, &(node_id as i64), &timestamp.num_nanoseconds().unwrap(), &(packet_id as i64), &self.capture_id()]).unwrap();
+
+		Some(self.connection.last_insert_rowid())
+	}
+
+	/// Links an NMT command to the state change it caused.
+	pub fn update_nmt_command(&self, id: i64, timediff: Duration, state: Option<NmtState>, packet_id: usize) {
+		if !self.recording.get() {
+			return;
+		}
+
+		let state_i64 = state.map(|s| (s as u8) as i64);
+
+		self.connection.execute("
+			UPDATE nmt_commands SET timediff_ns=$1, state=$2, state_packet_id=$3
+			WHERE id=$4",
+		&[&timediff.num_nanoseconds().unwrap(), &state_i64, &(packet_id as i64), &id]).unwrap();
+
+	}
+
+	/// Stores the IdentResponse of a node. Only the latest response per node is kept.
+	pub fn insert_ident(&self, node_id: u8, ident: &IdentResponse, timestamp: Duration, packet_id: usize) {
+		if !self.recording.get() {
+			return;
+		}
+
+		self.connection.execute("
+			INSERT OR REPLACE INTO ident (node_id, nmt_state, epl_version, feature_flags, mtu, poll_in_size, poll_out_size, response_time,
+			                              device_type, vendor_id, product_code, revision_number, serial_number, verify_conf_date,
+			                              verify_conf_time, ip_address, subnet_mask, default_gateway, host_name, timestamp, packet_id, capture_id)
+			VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22)",
+		&[&(node_id as i64), &ident.nmt_state.map(|s| (s as u8) as i64), &(ident.epl_version as i64), &(ident.feature_flags as i64),
+		  &(ident.mtu as i64), &(ident.poll_in_size as i64), &(ident.poll_out_size as i64), &(ident.response_time as i64),
+		  &(ident.device_type as i64), &(ident.vendor_id as i64), &(ident.product_code as i64), &(ident.revision_number as i64),
+		  &(ident.serial_number as i64), &(ident.verify_conf_date as i64), &(ident.verify_conf_time as i64),
+		  &&ident.ip_address[..], &&ident.subnet_mask[..], &&ident.default_gateway[..], &ident.host_name,
+		  &timestamp.num_nanoseconds().unwrap(), &(packet_id as i64), &self.capture_id()]).unwrap();
+
+	}
+
+	pub fn insert_static_errors(&self, node_id: u8, status: &StatusResponse, timestamp: Duration, packet_id: usize) {
+		if !self.recording.get() {
+			return;
+		}
+		self.connection.execute("
+			INSERT INTO static_errors (node_id, error_register, static_errors, en, ec, pr, rs, nmt_state, timestamp, packet_id, capture_id)
+			VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
+		&[&(node_id as i64), &(status.static_errors[0] as i64), &&status.static_errors[..], &status.en, &status.ec,
+		  &(status.pr as i64), &(status.rs as i64),
+		  &status.nmt_state.map(|s| (s as u8) as i64), &timestamp.num_nanoseconds().unwrap(), &(packet_id as i64), &self.capture_id()]).unwrap();
+	}
+
+	/// Stores an error/event entry unless the same entry of the node was already stored.
+	pub fn insert_error_entry(&self, node_id: u8, entry: &ErrorEntry, timestamp: Duration, packet_id: usize) {
+		if !self.recording.get() {
+			return;
+		}
+		self.connection.execute("
+			INSERT OR IGNORE INTO error_entries (node_id, entry_type, error_code, net_time_ns, additional_info, timestamp, packet_id, capture_id)
+			VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+		&[&(node_id as i64), &(entry.entry_type as i64), &(entry.error_code as i64), &timespec_to_ns(entry.timestamp),
+		  &(entry.additional_info as i64), &timestamp.num_nanoseconds().unwrap(), &(packet_id as i64), &self.capture_id()]).unwrap();
+	}
+
+	pub fn insert_vlan(&self, vlan_id: u16, outer_vlan_id: Option<u16>, packet_id: usize) {
+		if !self.recording.get() {
+			return;
+		}
+		self.connection.execute("
+			INSERT INTO vlan (vlan_id, outer_vlan_id, packet_id, capture_id)
 			VALUES ($1, $2, $3, $4)",
-		&[&(node_id as i64), &state_i64, &timestamp.num_nanoseconds().unwrap(), &(packet_id as i64)]).unwrap();
+		&[&(vlan_id as i64), &outer_vlan_id.map(|id| id as i64), &(packet_id as i64), &self.capture_id()]).unwrap();
+	}
+
+	pub fn insert_file(&self, file_id: usize, path: &str, packets: usize, first_ts: Option<Timespec>, last_ts: Option<Timespec>) {
+		self.connection.execute("
+			INSERT INTO files (file_id, path, packets, first_ts_ns, last_ts_ns, capture_id)
+			VALUES ($1, $2, $3, $4, $5, $6)",
+		&[&(file_id as i64), &path, &(packets as i64), &first_ts.map(timespec_to_ns), &last_ts.map(timespec_to_ns), &self.capture_id()]).unwrap();
+	}
+
+	/// Records that packets from `first_packet_id` on are read from `file_id`.
+	pub fn insert_file_run(&self, file_id: usize, first_packet_id: usize) {
+		if !self.recording.get() {
+			return;
+		}
+		self.connection.execute("
+			INSERT INTO file_runs (first_packet_id, file_id, capture_id)
+			VALUES ($1, $2, $3)",
+		&[&(first_packet_id as i64), &(file_id as i64), &self.capture_id()]).unwrap();
+	}
+
+	pub fn insert_section(&self, file_id: usize, section: &Section) {
+		self.connection.execute("
+			INSERT INTO sections (file_id, hardware, os, application, comment, capture_id)
+			VALUES ($1, $2, $3, $4, $5, $6)",
+		&[&(file_id as i64), &section.hardware, &section.os, &section.application, &section.comment, &self.capture_id()]).unwrap();
+	}
+
+	pub fn insert_interface(&self, file_id: usize, interface_id: usize, interface: &Interface, packets: usize) {
+		self.connection.execute("
+			INSERT INTO interfaces (file_id, interface_id, link_type, name, description, tsresol, tsoffset, packets, capture_id)
+			VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+		&[&(file_id as i64), &(interface_id as i64), &(interface.link_type as i64), &interface.name, &interface.description,
+		  &(interface.resolution.tsresol() as i64), &interface.offset, &(packets as i64), &self.capture_id()]).unwrap();
+	}
 
+	pub fn insert_comment(&self, packet_id: usize, file_id: usize, interface_id: usize, comment: &str) {
+		if !self.recording.get() {
+			return;
+		}
+		self.connection.execute("
+			INSERT INTO comments (packet_id, file_id, interface_id, comment, capture_id)
+			VALUES ($1, $2, $3, $4, $5)",
+		&[&(packet_id as i64), &(file_id as i64), &(interface_id as i64), &comment, &self.capture_id()]).unwrap();
 	}
 
-	pub fn get_response_stats(&self, table: &str, where_clause: String) -> Result<ResponseStats> {
+	/// Computes the stats of the `timediff_ns` column. Medians below a few times `resolution`
+	/// are flagged as coarse.
+	pub fn get_response_stats(&self, table: &str, where_clause: String, resolution: Resolution) -> Result<ResponseStats> {
 		
-		// note: table and where_clause are not escaed.
-		// however we solely work on temporary databases.
+		// note: table and where_clause are not escaped. They are built by the evaluation,
+		// except for the --filter expression which is SQL by design. Neither may come from
+		// anyone but the user running the analyzer, who may change the database file anyway.
+		// Rows without a time difference, like the first SoC, have no place in the stats.
+		let where_clause = format!("({}) AND timediff_ns IS NOT NULL", where_clause);
 
 		let get_percentile = |percentage: &str| -> Result<i64> {
 			
@@ -212,6 +1173,8 @@ impl Database {
 		let avg_int = avg as i64;
 		let jitter_abs = cmp::max(avg_int-min,max-avg_int);
 
+		let median = get_percentile("1/2")?;
+
 		Ok(ResponseStats {
 			min: min,
 			max: max,
@@ -219,8 +1182,9 @@ impl Database {
 			jitter_abs: jitter_abs,
 			jitter_rel: jitter_abs as f64 / avg,
 			quartile1: get_percentile("1/4")?,
-			median: get_percentile("1/2")?,
+			median,
 			quartile3: get_percentile("3/4")?,
+			coarse: (median as f64) < resolution.nanoseconds() * MIN_RESOLUTION_RATIO,
 		})
 
 		//Ok((min as u64,max as u64,avg,jitter_abs as u64,jitter_rel))
@@ -245,7 +1209,7 @@ OFFSET (SELECT
 */
 
 
-	pub fn get_raw(&self, where_clause: &str, sort: bool) -> Vec<(u64,String,u8)>  {
+	pub fn get_raw(&self, where_clause: &str, sort: bool) -> Vec<ResponseRow>  {
 		let mut result = Vec::new();
 		let order = if sort {
 			"ORDER BY timediff_ns DESC"
@@ -257,7 +1221,12 @@ OFFSET (SELECT
 					SELECT
 						timediff_ns,
 						type,
-						node_id
+						node_id,
+						capture_time_ns,
+						timestamp,
+						cycle,
+						request_packet_id,
+						packet_id
 					FROM response
 					WHERE {}
 					{}
@@ -266,15 +1235,350 @@ OFFSET (SELECT
 		let mut rows = stmt.query(&[]).unwrap();
 		while let Some(result_row) = rows.next() {
 			let row = result_row.unwrap();
-			let timediff = row.get::<i32, i64>(0) as u64;
-			let packet_type = row.get::<i32, String>(1);
-			let node_id = row.get::<i32, i64>(2) as u8;
-			result.push((timediff,packet_type,node_id));
+			result.push(ResponseRow {
+				timediff_ns: row.get::<i32, i64>(0) as u64,
+				packet_type: row.get(1),
+				node_id: row.get::<i32, i64>(2) as u8,
+				origin: origin_at(&row, 3),
+			});
+		}
+
+		result
+	}
+
+	/// Returns (node, frame type, number of frames without RD, number of frames) of all
+	/// PReqs sent while the MN was Operational and all PRes sent by Operational nodes.
+	pub fn get_not_ready(&self) -> Vec<(u8,String,usize,usize)> {
+		let mut result = Vec::new();
+		let mut stmt = self.connection.prepare(&format!("
+					SELECT node_id, type, SUM(NOT rd), COUNT(*)
+					FROM flags
+					WHERE (type=='preq' AND mn_state=={0}) OR (type=='pres' AND cn_state=={0})
+					GROUP BY node_id, type
+					ORDER BY node_id, type
+				", NmtState::Operational as u8)[..]).unwrap();
+		let row_iter = stmt.query_map(&[], |row| -> (i64,String,i64,i64) {
+			(row.get(0),row.get(1),row.get(2),row.get(3))
+		}).unwrap();
+		for row in row_iter.flatten() {
+			result.push((row.0 as u8, row.1, row.2 as usize, row.3 as usize));
+		}
+		result
+	}
+
+	/// Returns (node, PR, number of PRes, maximum RS) of all PRes with pending asynchronous requests.
+	pub fn get_request_priorities(&self) -> Vec<(u8,u8,usize,u8)> {
+		let mut result = Vec::new();
+		let mut stmt = self.connection.prepare("
+					SELECT node_id, pr, COUNT(*), MAX(rs)
+					FROM flags
+					WHERE type=='pres' AND rs>0
+					GROUP BY node_id, pr
+					ORDER BY node_id, pr
+				").unwrap();
+		let row_iter = stmt.query_map(&[], |row| -> (i64,i64,i64,i64) {
+			(row.get(0),row.get(1),row.get(2),row.get(3))
+		}).unwrap();
+		for row in row_iter.flatten() {
+			result.push((row.0 as u8, row.1 as u8, row.2 as usize, row.3 as u8));
+		}
+		result
+	}
+
+	/// Returns (capture time, NetTime, RelativeTime) of every SoC in capture order.
+	/// Capture time and NetTime are absolute nanoseconds, RelativeTime is in microseconds.
+	pub fn get_soc_times(&self) -> Vec<(i64,i64,i64)> {
+		let mut result = Vec::new();
+		let mut stmt = self.connection.prepare("SELECT capture_time_ns, net_time_ns, relative_time_us FROM soc ORDER BY id").unwrap();
+		let soc_iter = stmt.query_map(&[], |row| -> (i64,i64,i64) {
+			(row.get(0),row.get(1),row.get(2))
+		}).unwrap();
+		for soc in soc_iter.flatten() {
+			result.push(soc);
+		}
+		result
+	}
+
+	/// Returns (cycle, timestamp, node, frame type, index, subindex, value) of all extracted PDO values.
+	pub fn get_pdo_values(&self) -> Vec<(i64,i64,u8,String,u16,u8,String)> {
+		let mut result = Vec::new();
+		let mut stmt = self.connection.prepare("
+					SELECT cycle, timestamp, node_id, type, object_index, object_subindex, CAST(value AS TEXT)
+					FROM pdo_values
+					ORDER BY id
+				").unwrap();
+		let row_iter = stmt.query_map(&[], |row| -> (i64,i64,i64,String,i64,i64,String) {
+			(row.get(0),row.get(1),row.get(2),row.get(3),row.get(4),row.get(5),row.get(6))
+		}).unwrap();
+		for row in row_iter.flatten() {
+			result.push((row.0, row.1, row.2 as u8, row.3, row.4 as u16, row.5 as u8, row.6));
+		}
+		result
+	}
+
+	/// Returns all SDO transfers in the order they were started.
+	pub fn get_sdo_transfers(&self) -> Vec<SdoTransferRow> {
+		let mut result = Vec::new();
+		let mut stmt = self.connection.prepare("
+					SELECT client, node_id, command, object_index, object_subindex, timediff_ns, cycles, bytes, retransmissions, result, timestamp, packet_id
+					FROM sdo_transfer
+					ORDER BY packet_id
+				").unwrap();
+		let row_iter = stmt.query_map(&[], |row| -> SdoTransferRow {
+			SdoTransferRow {
+				client: row.get::<i32, i64>(0) as u8,
+				server: row.get::<i32, i64>(1) as u8,
+				command: row.get(2),
+				object: row.get_checked::<i32, i64>(3).ok().map(|index| (index as u16, row.get::<i32, i64>(4) as u8)),
+				duration_ns: row.get(5),
+				cycles: row.get::<i32, i64>(6) as usize,
+				bytes: row.get::<i32, i64>(7) as u64,
+				retransmissions: row.get::<i32, i64>(8) as usize,
+				result: row.get(9),
+				timestamp: row.get(10),
+				packet_id: row.get::<i32, i64>(11) as usize,
+			}
+		}).unwrap();
+		for row in row_iter.flatten() {
+			result.push(row);
+		}
+		result
+	}
+
+	/// Returns (aborting node, object, abort code, count) of all SDO aborts.
+	pub fn get_sdo_aborts(&self) -> Vec<(u8,Option<ObjectId>,u32,usize)> {
+		let mut result = Vec::new();
+		let mut stmt = self.connection.prepare("
+					SELECT src, object_index, object_subindex, abort_code, COUNT(*)
+					FROM sdo
+					WHERE abort AND abort_code IS NOT NULL
+					GROUP BY src, object_index, object_subindex, abort_code
+					ORDER BY src, object_index, object_subindex, abort_code
+				").unwrap();
+		let row_iter = stmt.query_map(&[], |row| -> (u8,Option<ObjectId>,u32,usize) {
+			(row.get::<i32, i64>(0) as u8,
+			 row.get_checked::<i32, i64>(1).ok().map(|index| (index as u16, row.get::<i32, i64>(2) as u8)),
+			 row.get::<i32, i64>(3) as u32,
+			 row.get::<i32, i64>(4) as usize)
+		}).unwrap();
+		for row in row_iter.flatten() {
+			result.push(row);
+		}
+		result
+	}
+
+	/// Returns all NMT commands together with the state change they caused.
+	pub fn get_nmt_commands(&self) -> Vec<NmtCommandRow> {
+		let mut result = Vec::new();
+		let mut stmt = self.connection.prepare("
+					SELECT node_id, command, timestamp, packet_id, timediff_ns, state
+					FROM nmt_commands
+					ORDER BY id
+				").unwrap();
+		let row_iter = stmt.query_map(&[], |row| -> NmtCommandRow {
+			NmtCommandRow {
+				node_id: row.get::<i32, i64>(0) as u8,
+				command: row.get(1),
+				timestamp: row.get(2),
+				packet_id: row.get::<i32, i64>(3) as usize,
+				delay_ns: row.get(4),
+				state: row.get::<i32, Option<i64>>(5).and_then(|s| NmtState::from_u8(s as u8)),
+			}
+		}).unwrap();
+		for row in row_iter.flatten() {
+			result.push(row);
+		}
+		result
+	}
+
+	/// Returns the latest IdentResponse of each node.
+	pub fn get_inventory(&self) -> Vec<(u8,IdentResponse)> {
+		let mut result = Vec::new();
+		let mut stmt = self.connection.prepare("
+					SELECT node_id, nmt_state, epl_version, feature_flags, mtu, poll_in_size, poll_out_size, response_time,
+					       device_type, vendor_id, product_code, revision_number, serial_number, verify_conf_date,
+					       verify_conf_time, ip_address, subnet_mask, default_gateway, host_name
+					FROM ident
+					ORDER BY node_id
+				").unwrap();
+		let row_iter = stmt.query_map(&[], |row| -> (u8,IdentResponse) {
+			let address = |i: i32| -> [u8; 4] {
+				let bytes: Vec<u8> = row.get(i);
+				[bytes[0], bytes[1], bytes[2], bytes[3]]
+			};
+			(row.get::<i32, i64>(0) as u8, IdentResponse {
+				nmt_state: row.get::<i32, Option<i64>>(1).and_then(|s| NmtState::from_u8(s as u8)),
+				epl_version: row.get::<i32, i64>(2) as u8,
+				feature_flags: row.get::<i32, i64>(3) as u32,
+				mtu: row.get::<i32, i64>(4) as u16,
+				poll_in_size: row.get::<i32, i64>(5) as u16,
+				poll_out_size: row.get::<i32, i64>(6) as u16,
+				response_time: row.get::<i32, i64>(7) as u32,
+				device_type: row.get::<i32, i64>(8) as u32,
+				vendor_id: row.get::<i32, i64>(9) as u32,
+				product_code: row.get::<i32, i64>(10) as u32,
+				revision_number: row.get::<i32, i64>(11) as u32,
+				serial_number: row.get::<i32, i64>(12) as u32,
+				verify_conf_date: row.get::<i32, i64>(13) as u32,
+				verify_conf_time: row.get::<i32, i64>(14) as u32,
+				ip_address: address(15),
+				subnet_mask: address(16),
+				default_gateway: address(17),
+				host_name: row.get(18),
+			})
+		}).unwrap();
+		for row in row_iter.flatten() {
+			result.push(row);
+		}
+		result
+	}
+
+	/// Returns (node, static error bit field, timestamp, packet ID) of every change of a static error bit field.
+	pub fn get_static_errors(&self) -> Vec<(u8,Vec<u8>,i64,usize)> {
+		let mut result = Vec::new();
+		let mut stmt = self.connection.prepare("SELECT node_id, static_errors, timestamp, packet_id FROM static_errors ORDER BY node_id, id").unwrap();
+		let row_iter = stmt.query_map(&[], |row| -> (u8,Vec<u8>,i64,usize) {
+			(row.get::<i32, i64>(0) as u8, row.get(1), row.get(2), row.get::<i32, i64>(3) as usize)
+		}).unwrap();
+		for row in row_iter.flatten() {
+			result.push(row);
+		}
+		result
+	}
+
+	/// Returns (node, entry, timestamp, packet ID) of all distinct error/event entries.
+	pub fn get_error_entries(&self) -> Vec<(u8,ErrorEntry,i64,usize)> {
+		let mut result = Vec::new();
+		let mut stmt = self.connection.prepare("
+					SELECT node_id, entry_type, error_code, net_time_ns, additional_info, timestamp, packet_id
+					FROM error_entries
+					ORDER BY node_id, id
+				").unwrap();
+		let row_iter = stmt.query_map(&[], |row| -> (u8,ErrorEntry,i64,usize) {
+			let net_time: i64 = row.get(3);
+			(row.get::<i32, i64>(0) as u8, ErrorEntry {
+				entry_type: row.get::<i32, i64>(1) as u16,
+				error_code: row.get::<i32, i64>(2) as u16,
+				timestamp: Timespec { sec: net_time / 1_000_000_000, nsec: (net_time % 1_000_000_000) as i32 },
+				additional_info: row.get::<i32, i64>(4) as u64,
+			}, row.get(5), row.get::<i32, i64>(6) as usize)
+		}).unwrap();
+		for row in row_iter.flatten() {
+			result.push(row);
+		}
+		result
+	}
+
+	/// Returns (VLAN ID, outer VLAN ID, number of frames) of all VLANs.
+	pub fn get_vlans(&self) -> Vec<(u16,Option<u16>,usize)> {
+		let mut result = Vec::new();
+		let mut stmt = self.connection.prepare("SELECT vlan_id, outer_vlan_id, COUNT(*) FROM vlan GROUP BY outer_vlan_id, vlan_id ORDER BY outer_vlan_id, vlan_id").unwrap();
+		let row_iter = stmt.query_map(&[], |row| -> (u16,Option<u16>,usize) {
+			(row.get::<i32, i64>(0) as u16, row.get::<i32, Option<i64>>(1).map(|id| id as u16), row.get::<i32, i64>(2) as usize)
+		}).unwrap();
+		for row in row_iter.flatten() {
+			result.push(row);
+		}
+		result
+	}
+
+	/// Returns all files ordered by their first packet.
+	pub fn get_files(&self) -> Vec<FileRow> {
+		let mut result = Vec::new();
+		let mut stmt = self.connection.prepare("SELECT file_id, path, packets, first_ts_ns, last_ts_ns FROM files ORDER BY first_ts_ns, file_id").unwrap();
+		let row_iter = stmt.query_map(&[], |row| -> FileRow {
+			FileRow {
+				file_id: row.get::<i32, i64>(0) as usize,
+				path: row.get(1),
+				packets: row.get::<i32, i64>(2) as usize,
+				first_ts_ns: row.get(3),
+				last_ts_ns: row.get(4),
+			}
+		}).unwrap();
+		for row in row_iter.flatten() {
+			result.push(row);
+		}
+		result
+	}
+
+	/// Translates a packet ID of merged files into the file ID and the frame number within
+	/// that file. Returns `None` if the packet was not read from any file.
+	pub fn locate_packet(&self, packet_id: usize) -> Option<(usize,usize)> {
+		let (file_id, first_packet_id): (i64, i64) = self.connection.query_row("
+			SELECT file_id, first_packet_id
+			FROM file_runs
+			WHERE capture_id = $1 AND first_packet_id <= $2
+			ORDER BY first_packet_id DESC
+			LIMIT 1",
+		&[&self.capture_id(), &(packet_id as i64)], |row| (row.get(0), row.get(1))).ok()?;
+		// Earlier runs of the same file end where the next run of any file starts.
+		let before: i64 = self.connection.query_row("
+			SELECT IFNULL(SUM((SELECT MIN(n.first_packet_id) FROM file_runs n
+			                   WHERE n.capture_id = r.capture_id AND n.first_packet_id > r.first_packet_id) - r.first_packet_id), 0)
+			FROM file_runs r
+			WHERE r.capture_id = $1 AND r.file_id = $2 AND r.first_packet_id < $3",
+		&[&self.capture_id(), &file_id, &first_packet_id], |row| row.get(0)).ok()?;
+		Some((file_id as usize, (before + packet_id as i64 - first_packet_id + 1) as usize))
+	}
+
+	pub fn get_sections(&self) -> Vec<Section> {
+		let mut result = Vec::new();
+		let mut stmt = self.connection.prepare("SELECT hardware, os, application, comment FROM sections ORDER BY id").unwrap();
+		let row_iter = stmt.query_map(&[], |row| -> Section {
+			Section {
+				hardware: row.get(0),
+				os: row.get(1),
+				application: row.get(2),
+				comment: row.get(3),
+			}
+		}).unwrap();
+		for row in row_iter.flatten() {
+			result.push(row);
 		}
+		result
+	}
 
+	/// Returns file ID, interface ID, description and number of packets of all interfaces.
+	pub fn get_interfaces(&self) -> Vec<(usize,usize,Interface,usize)> {
+		let mut result = Vec::new();
+		let mut stmt = self.connection.prepare("
+					SELECT file_id, interface_id, link_type, name, description, tsresol, tsoffset, packets
+					FROM interfaces
+					ORDER BY file_id, interface_id
+				").unwrap();
+		let row_iter = stmt.query_map(&[], |row| -> (usize,usize,Interface,usize) {
+			(row.get::<i32, i64>(0) as usize, row.get::<i32, i64>(1) as usize, Interface {
+				link_type: row.get::<i32, i64>(2) as u16,
+				name: row.get(3),
+				description: row.get(4),
+				resolution: Resolution::from_tsresol(row.get::<i32, i64>(5) as u8),
+				offset: row.get(6),
+			}, row.get::<i32, i64>(7) as usize)
+		}).unwrap();
+		for row in row_iter.flatten() {
+			result.push(row);
+		}
 		result
 	}
 
+	/// Returns packet ID, file ID, interface ID and text of all packet comments.
+	pub fn get_comments(&self) -> Vec<(usize,usize,usize,String)> {
+		let mut result = Vec::new();
+		let mut stmt = self.connection.prepare("SELECT packet_id, file_id, interface_id, comment FROM comments ORDER BY packet_id").unwrap();
+		let row_iter = stmt.query_map(&[], |row| -> (usize,usize,usize,String) {
+			(row.get::<i32, i64>(0) as usize, row.get::<i32, i64>(1) as usize, row.get::<i32, i64>(2) as usize, row.get(3))
+		}).unwrap();
+		for row in row_iter.flatten() {
+			result.push(row);
+		}
+		result
+	}
+
+	/// Returns the largest row ID of a table, or 0 if it is empty.
+	pub fn get_last_id(&self, table: &str) -> i64 {
+		self.connection.query_row(&format!("SELECT IFNULL(MAX(id), 0) FROM {}", table)[..], &[], |row| row.get(0)).unwrap()
+	}
+
 	pub fn get_nodes(&self, table: &str, where_clause: String) -> Vec<u8> {
 		let mut result = Vec::new();
 		let mut stmt = self.connection.prepare(&format!("SELECT node_id FROM {} WHERE {} GROUP BY node_id",table,where_clause)[..]).unwrap();
@@ -287,32 +1591,108 @@ OFFSET (SELECT
 		return result;
 	}
 
-	pub fn get_errors(&self) -> Vec<(u8,String,NmtState,NmtState,usize)> {
+	/// Returns the errors grouped by node, type and states.
+	pub fn get_errors(&self) -> Vec<ErrorGroup> {
+		self.get_errors_since(0)
+	}
+
+	/// Returns the errors inserted after row `last_id`, grouped like `get_errors`.
+	pub fn get_errors_since(&self, last_id: i64) -> Vec<ErrorGroup> {
 		let mut result = Vec::new();
-		let mut stmt = self.connection.prepare("SELECT node_id, type, cn_state, mn_state, COUNT(type) FROM errors GROUP BY type,cn_state,mn_state ORDER BY node_id, cn_state, mn_state").unwrap();
-		let node_iter = stmt.query_map(&[], |row| -> (i64,String,i64,i64,i64) {
-			(row.get(0),row.get(1),row.get_checked(2).unwrap_or(NmtState::Unknown as i64),row.get_checked(3).unwrap_or(NmtState::Unknown as i64),row.get(4))
+		let mut stmt = self.connection.prepare(&format!("
+			SELECT node_id, type, cn_state, mn_state, COUNT(type), MIN(packet_id), MAX(packet_id)
+			FROM errors
+			WHERE id > {}
+			GROUP BY node_id, type, cn_state, mn_state
+			ORDER BY node_id, cn_state, mn_state", last_id)[..]).unwrap();
+		let node_iter = stmt.query_map(&[], |row| -> ErrorGroup {
+			let state = |column| NmtState::from_u8(row.get_checked::<i32, i64>(column).unwrap_or(NmtState::Unknown as i64) as u8).expect("Invalid NmtState in database!");
+			ErrorGroup {
+				node_id: row.get::<i32, i64>(0) as u8,
+				error_type: row.get(1),
+				cn_state: state(2),
+				mn_state: state(3),
+				count: row.get::<i32, i64>(4) as usize,
+				first_packet_id: row.get::<i32, i64>(5) as usize,
+				last_packet_id: row.get::<i32, i64>(6) as usize,
+			}
 		}).unwrap();
-		for node in node_iter {
-			if let Ok(n) = node {
-				result.push((n.0 as u8, n.1, NmtState::from_u8(n.2 as u8).expect("Invalid NmtState in database!"), NmtState::from_u8(n.3 as u8).expect("Invalid NmtState in database!"), n.4 as usize));
-			};
+		for group in node_iter.flatten() {
+			result.push(group);
 		}
-		return result;
+		result
 	}
 
-	pub fn get_state_changes(&self) -> Vec<(u8,NmtState,i64,i64)> {
+	/// Returns node, state, timestamp, packet ID and source file ID of all state changes.
+	pub fn get_state_changes(&self) -> Vec<(u8,NmtState,i64,i64,Option<usize>)> {
 		let mut result = Vec::new();
-		let mut stmt = self.connection.prepare("SELECT node_id, state, timestamp, packet_id FROM state_changes ORDER BY timestamp").unwrap();
-		let node_iter = stmt.query_map(&[], |row| -> (i64,i64,i64,i64) {
-			(row.get(0),row.get_checked(1).unwrap_or(NmtState::Unknown as i64),row.get(2),row.get(3))
+		let mut stmt = self.connection.prepare("
+					SELECT node_id, state, timestamp, packet_id,
+					       (SELECT file_id FROM file_runs r WHERE r.capture_id == s.capture_id AND r.first_packet_id <= s.packet_id ORDER BY r.first_packet_id DESC LIMIT 1)
+					FROM state_changes s
+					ORDER BY timestamp
+				").unwrap();
+		let node_iter = stmt.query_map(&[], |row| -> (i64,i64,i64,i64,Option<i64>) {
+			(row.get(0),row.get_checked(1).unwrap_or(NmtState::Unknown as i64),row.get(2),row.get(3),row.get(4))
 		}).unwrap();
 		for node in node_iter {
 			if let Ok(n) = node {
-				result.push((n.0 as u8, NmtState::from_u8(n.1 as u8).expect("Invalid NmtState in database!"), n.2, n.3));
+				result.push((n.0 as u8, NmtState::from_u8(n.1 as u8).expect("Invalid NmtState in database!"), n.2, n.3, n.4.map(|id| id as usize)));
 			};
 		}
 		return result;
 	}
 
-}
\ No newline at end of file
+}
+
+/// Reads the columns capture_time_ns, timestamp, cycle, request_packet_id and packet_id
+/// starting at `column`.
+fn origin_at(row: &Row, column: i32) -> Origin {
+	Origin {
+		capture_time: ns_to_timespec(row.get(column)),
+		timestamp: Duration::nanoseconds(row.get(column+1)),
+		cycle: row.get::<i32, i64>(column+2) as u64,
+		request_packet_id: row.get::<i32, Option<i64>>(column+3).map(|id| id as usize),
+		packet_id: row.get::<i32, i64>(column+4) as usize,
+	}
+}
+
+fn ns_to_timespec(ns: i64) -> Timespec {
+	Timespec::new(ns.div_euclid(1_000_000_000), ns.rem_euclid(1_000_000_000) as i32)
+}
+
+fn timespec_to_ns(ts: Timespec) -> i64 {
+	ts.sec * 1_000_000_000 + ts.nsec as i64
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use status::StatusResponse;
+	use status::tests::{entry,status_response};
+
+	#[test]
+	fn error_entries_are_stored_once() {
+		let db = Database::new();
+		let repeated = entry(0x9001, 0x8611, 1000, 500, 7);
+		let first = StatusResponse::parse(&status_response([0; 8], ::std::slice::from_ref(&repeated))).unwrap();
+		let second = StatusResponse::parse(&status_response([0; 8], &[repeated, entry(0x9001, 0x8612, 1001, 0, 7)])).unwrap();
+
+		for entry in &first.entries {
+			db.insert_error_entry(3, entry, Duration::milliseconds(10), 10);
+		}
+		for entry in &second.entries {
+			db.insert_error_entry(3, entry, Duration::milliseconds(20), 20);
+		}
+		// The same entry of another node is distinct.
+		db.insert_error_entry(4, &first.entries[0], Duration::milliseconds(30), 30);
+
+		let entries = db.get_error_entries();
+		assert_eq!(entries.len(), 3);
+		assert_eq!((entries[0].0, entries[0].1.error_code, entries[0].3), (3, 0x8611, 10));
+		assert_eq!((entries[1].0, entries[1].1.error_code, entries[1].3), (3, 0x8612, 20));
+		assert_eq!((entries[2].0, entries[2].1.error_code, entries[2].3), (4, 0x8611, 30));
+	}
+
+}
@@ -16,9 +16,39 @@
 //! The Evaluation module is able to pretty print the results using the data from the Database module.
 
 use database::*;
+use sdo::abort_text;
+use ident::IdentResponse;
+use frame::LinkType;
+use capture::Resolution;
+use time;
+use std::cmp;
+
+/// Categories of the aggregate report: title, table and condition.
+const AGGREGATE_CATEGORIES: [(&str, &str, &str); 14] = [
+	("Cycle/SoC", "soc", "1==1"),
+	("SoC-PReq", "cycle_phase", "phase=='soc_preq'"),
+	("Isochronous", "cycle_phase", "phase=='isochronous'"),
+	("PRes-SoA", "cycle_phase", "phase=='pres_soa'"),
+	("SoA-Async", "cycle_phase", "phase=='soa_async'"),
+	("Idle", "cycle_phase", "phase=='idle'"),
+	("Responses", "response", "type!='mn_turnaround'"),
+	("PRes", "response", "type=='pres'"),
+	("Ident", "response", "type=='ident'"),
+	("Status", "response", "type=='status'"),
+	("SDO", "response", "type=='sdo'"),
+	("NMT", "response", "type=='nmt_command'"),
+	("Veth", "response", "type=='veth'"),
+	("MN turnaround", "response", "type=='mn_turnaround'"),
+];
+
+/// Number of samples printed for the NetTime offset over time.
+const OFFSET_SAMPLES: usize = 10;
+
+/// Number of RelativeTime jumps printed in detail.
+const MAX_PRINTED_JUMPS: usize = 10;
 
 /// Prints a single line in the result table.
-macro_rules! println_stats { ( $name:expr, $stats:expr  ) => ( println!("{:<9}  min = {:>10}ns  25% = {:>10}ns  50% = {:>10}ns avg = {:>10}ns  75% = {:>10}ns  max = {:>10}ns  jitter_abs = {:>9}ns  jitter_rel = {:>6.2}%",
+macro_rules! println_stats { ( $name:expr, $stats:expr  ) => ( println!("{:<9}  min = {:>10}ns  25% = {:>10}ns  50% = {:>10}ns avg = {:>10}ns  75% = {:>10}ns  max = {:>10}ns  jitter_abs = {:>9}ns  jitter_rel = {:>6.2}%{}",
 	$name,
 	Evaluation::group_digits($stats.min),
 	Evaluation::group_digits($stats.quartile1),
@@ -27,12 +57,18 @@ macro_rules! println_stats { ( $name:expr, $stats:expr  ) => ( println!("{:<9}
 	Evaluation::group_digits($stats.quartile3),
 	Evaluation::group_digits($stats.max),
 	Evaluation::group_digits($stats.jitter_abs),
-	$stats.jitter_rel*100f64)
+	$stats.jitter_rel*100f64,
+	if $stats.coarse { "  (resolution too coarse)" } else { "" })
 ); }
 
 pub trait StatPrinter {
 	fn print_stats_header();
 	fn print_stats(category: &str, node: Option<u8>, prefix: &str, stats: &ResponseStats);
+	fn print_inventory_header();
+	fn print_inventory(node: u8, ident: &IdentResponse);
+	fn print_aggregate_header();
+	/// Prints the stats of one capture or, without capture, the pooled stats of a category.
+	fn print_aggregate(category: &str, capture: Option<&str>, prefix: &str, stats: &ResponseStats);
 }
 
 pub struct StdoutPrinter;
@@ -52,29 +88,78 @@ impl StatPrinter for StdoutPrinter {
 
 	}
 
+	fn print_inventory_header() {
+		println!("\nInventory:");
+	}
+
+	fn print_inventory(node: u8, ident: &IdentResponse) {
+		println!("[{:>3}] vendor=0x{:08X} product=0x{:08X} revision=0x{:08X} serial=0x{:08X} device_type=0x{:08X} epl={} features=0x{:08X}",
+			node, ident.vendor_id, ident.product_code, ident.revision_number, ident.serial_number, ident.device_type,
+			ident.epl_version_string(), ident.feature_flags);
+		println!("      mtu={} poll_in={} poll_out={} response_time={}ns ip={} host_name=\"{}\" configured={}",
+			ident.mtu, ident.poll_in_size, ident.poll_out_size, Evaluation::group_digits(ident.response_time as i64),
+			ident.ip_address_string(), ident.host_name, ident.verify_conf_string());
+	}
+
+	fn print_aggregate_header() {
+		println!("\nAggregate Statistics (first line of each category pooled over all captures):");
+	}
+
+	fn print_aggregate(category: &str, capture: Option<&str>, prefix: &str, stats: &ResponseStats) {
+		println_stats!(&format!("{}{}",prefix,capture.unwrap_or(category)),stats);
+	}
+
 }
 
 pub struct CsvPrinter;
 impl StatPrinter for CsvPrinter {
 	
 	fn print_stats_header() {
-		println!("title,node,min,quart1,median,avg,quart3,max,jitter_abs,jitter_rel");
+		println!("title,node,min,quart1,median,avg,quart3,max,jitter_abs,jitter_rel,coarse");
 	}
 
 	fn print_stats(category: &str, node: Option<u8>, _: &str, stats: &ResponseStats) {
-		println!("{},{},{},{},{},{},{},{},{},{}",category,node.unwrap_or(0),stats.min,stats.quartile1,stats.median,stats.avg,stats.quartile3,stats.max,stats.jitter_abs,stats.jitter_rel);
+		println!("{},{},{},{},{},{},{},{},{},{},{}",category,node.unwrap_or(0),stats.min,stats.quartile1,stats.median,stats.avg,stats.quartile3,stats.max,stats.jitter_abs,stats.jitter_rel,stats.coarse);
+	}
+
+	fn print_inventory_header() {
+		println!("node,vendor_id,product_code,revision_number,serial_number,device_type,epl_version,feature_flags,mtu,poll_in_size,poll_out_size,response_time,ip_address,host_name,verify_conf");
+	}
+
+	fn print_inventory(node: u8, ident: &IdentResponse) {
+		println!("{},0x{:08X},0x{:08X},0x{:08X},0x{:08X},0x{:08X},{},0x{:08X},{},{},{},{},{},{},{}",
+			node, ident.vendor_id, ident.product_code, ident.revision_number, ident.serial_number, ident.device_type,
+			ident.epl_version_string(), ident.feature_flags, ident.mtu, ident.poll_in_size, ident.poll_out_size,
+			ident.response_time, ident.ip_address_string(), ident.host_name, ident.verify_conf_string());
+	}
+
+	fn print_aggregate_header() {
+		println!("title,capture,min,quart1,median,avg,quart3,max,jitter_abs,jitter_rel,coarse");
+	}
+
+	fn print_aggregate(category: &str, capture: Option<&str>, _: &str, stats: &ResponseStats) {
+		println!("{},{},{},{},{},{},{},{},{},{},{}",category,capture.unwrap_or("pooled"),stats.min,stats.quartile1,stats.median,stats.avg,stats.quartile3,stats.max,stats.jitter_abs,stats.jitter_rel,stats.coarse);
 	}
 
 }
 
 
 pub struct Evaluation<'a> {
-	db: &'a mut Database,
+	db: &'a Database,
+}
+
+/// Last row IDs covered by a live report. The next report only covers newer rows.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReportMark {
+	soc: i64,
+	cycle_phase: i64,
+	response: i64,
+	errors: i64,
 }
 
 impl<'a> Evaluation<'a> {
 
-	pub fn new(database: &'a mut Database) -> Self {
+	pub fn new(database: &'a Database) -> Self {
 		Evaluation {
 			db: database,
 		}
@@ -83,52 +168,345 @@ impl<'a> Evaluation<'a> {
 	pub fn print_metadata<P: StatPrinter>(&self) {
 		println!("\nTotal capture time: {}s", self.db.get_total_time().num_milliseconds() as f64 / 1000f64);
 		println!("Total number of packets: {}", self.db.get_total_num_packets());
+		println!("Timestamp resolution: {}", self.db.get_resolution());
+		let files = self.db.get_files();
+		// Interfaces and comments are only labeled with their file when files were merged.
+		let merged = files.len() > 1;
+		if merged {
+			println!("Merged files:");
+			let mut previous_end: Option<i64> = None;
+			for file in &files {
+				let gap = match (previous_end, file.first_ts_ns) {
+					(Some(end), Some(start)) if start >= end => format!(", gap to previous file {}ns", Evaluation::group_digits(start - end)),
+					(Some(end), Some(start)) => format!(", overlaps previous file by {}ns", Evaluation::group_digits(end - start)),
+					_ => String::new(),
+				};
+				println!("[{}] {}: {} packets{}", file.file_id, file.path, file.packets, gap);
+				previous_end = cmp::max(previous_end, file.last_ts_ns);
+			}
+		}
+		let file_label = |file_id: usize| if merged { format!("[{}] ", file_id) } else { String::new() };
+		for section in self.db.get_sections() {
+			if let Some(hardware) = section.hardware {
+				println!("Capture hardware: {}", hardware);
+			}
+			if let Some(os) = section.os {
+				println!("Capture OS: {}", os);
+			}
+			if let Some(application) = section.application {
+				println!("Capture application: {}", application);
+			}
+			if let Some(comment) = section.comment {
+				println!("Section comment: {}", comment);
+			}
+		}
+		for (file_id, interface_id, interface, packets) in self.db.get_interfaces() {
+			let link_type = match LinkType::from_dlt(interface.link_type as i32) {
+				Some(link_type) => format!("{:?}", link_type),
+				None => format!("unsupported link type {}", interface.link_type),
+			};
+			println!("{}Interface {}: {}{}, {}, resolution {}, offset {}s, {} packets",
+				file_label(file_id), interface_id,
+				interface.name.as_ref().map_or("unnamed", |n| &n[..]),
+				interface.description.as_ref().map_or(String::new(), |d| format!(" ({})", d)),
+				link_type, interface.resolution, interface.offset, packets);
+		}
+		for (packet_id, file_id, interface_id, comment) in self.db.get_comments() {
+			println!("{}Comment on packet {} (interface {}): {}", file_label(file_id), packet_id, interface_id, comment);
+		}
+		for (vlan_id, outer_vlan_id, count) in self.db.get_vlans() {
+			match outer_vlan_id {
+				Some(outer) => println!("VLAN {}.{}: {} packets", outer, vlan_id, count),
+				None => println!("VLAN {}: {} packets", vlan_id, count),
+			}
+		}
+	}
+
+	pub fn print_inventory<P: StatPrinter>(&self) {
+		P::print_inventory_header();
+		for (node, ident) in self.db.get_inventory() {
+			P::print_inventory(node, &ident);
+		}
 	}
 
 	pub fn print_errors<P: StatPrinter>(&self) {
 		println!("\nErrors:");
 		println!("Notice: Missing Ident Responses from [253] (diagnostic device) and missing responses when CN state is Off are regular.");
-		for row in self.db.get_errors() {
-			println!("[{:>3}] {:>3}x {:<30} (CN:{:?} MN:{:?})", row.0, row.4, row.1, row.2, row.3);
+		let merged = self.db.get_files().len() > 1;
+		for group in self.db.get_errors() {
+			self.print_error_group(&group, merged);
+		}
+	}
+
+	fn print_error_group(&self, group: &ErrorGroup, merged: bool) {
+		let frames = if merged {
+			// Merged packet IDs cannot be found in Wireshark, the frames of each file can.
+			let first = self.describe_packet(group.first_packet_id);
+			if group.count == 1 {
+				first
+			} else {
+				format!("{} .. {}", first, self.describe_packet(group.last_packet_id))
+			}
+		} else if group.count == 1 {
+			format!("frame {}", group.first_packet_id)
+		} else {
+			format!("frames {}..{}", group.first_packet_id, group.last_packet_id)
+		};
+		println!("[{:>3}] {:>3}x {:<30} (CN:{:?} MN:{:?}) {}", group.node_id, group.count, group.error_type,
+			group.cn_state, group.mn_state, frames);
+	}
+
+	fn describe_packet(&self, packet_id: usize) -> String {
+		match self.db.locate_packet(packet_id) {
+			Some((file_id, frame)) => format!("file {}, frame {}", file_id, frame),
+			None => format!("packet {}", packet_id),
 		}
 	}
 
 	pub fn print_state_changes<P: StatPrinter>(&self) {
 		println!("\nState Changes:");
+		let merged = self.db.get_files().len() > 1;
 		for row in self.db.get_state_changes() {
-			println!("{:>5} {:>14}ns [{:>3}] {:?}", Evaluation::group_digits(row.3),
-				Evaluation::group_digits(row.2), row.0, row.1);
+			let file = match row.4 {
+				Some(file_id) if merged => format!(" (file {})", file_id),
+				_ => String::new(),
+			};
+			println!("{:>5} {:>14}ns [{:>3}] {:?}{}", Evaluation::group_digits(row.3),
+				Evaluation::group_digits(row.2), row.0, row.1, file);
 		}
 	}
 
-	pub fn print_stats<P: StatPrinter>(&self) {
+	pub fn print_clock<P: StatPrinter>(&self) {
+
+		println!("\nMN Clock:");
+
+		let samples = self.db.get_soc_times();
+		if samples.len() < 2 {
+			println!("Not enough SoCs to compare the MN clock with the capture clock.");
+			return;
+		}
+
+		let (first_capture, first_net, first_rel) = samples[0];
+		let first_offset = first_net - first_capture;
+
+		// Both MN clocks relative to the first SoC, plotted against the capture clock.
+		let net: Vec<(f64,f64)> = samples.iter().map(|s| ((s.0-first_capture) as f64, (s.1-first_net) as f64)).collect();
+		let rel: Vec<(f64,f64)> = samples.iter().map(|s| ((s.0-first_capture) as f64, ((s.2-first_rel)*1000) as f64)).collect();
+
+		println!("NetTime drift:      {}", Evaluation::format_ppm(Evaluation::drift_ppm(&net)));
+		println!("RelativeTime drift: {}", Evaluation::format_ppm(Evaluation::drift_ppm(&rel)));
+		println!("Initial offset (NetTime - capture time): {}ns", Evaluation::group_digits(first_offset));
+
+		println!("Offset change over time:");
+		let step = cmp::max(1, samples.len() / OFFSET_SAMPLES);
+		// The last sample is added unless the steps end on it anyway.
+		let last = if (samples.len() - 1).is_multiple_of(step) { None } else { samples.last() };
+		for s in samples.iter().step_by(step).chain(last) {
+			println!("{:>16}ns {:>12}ns", Evaluation::group_digits(s.0-first_capture),
+				Evaluation::group_digits(s.1-s.0-first_offset));
+		}
+
+		// A jump is a RelativeTime step that differs from the capture time step by more than half a cycle.
+		let mut jumps = 0;
+		for w in samples.windows(2) {
+			let capture_diff = w[1].0 - w[0].0;
+			let rel_diff = (w[1].2 - w[0].2) * 1000;
+			if (rel_diff - capture_diff).abs() > capture_diff / 2 {
+				if jumps < MAX_PRINTED_JUMPS {
+					println!("RelativeTime jump at {:>16}ns: {:>12}ns expected, {:>12}ns observed",
+						Evaluation::group_digits(w[1].0-first_capture),
+						Evaluation::group_digits(capture_diff),
+						Evaluation::group_digits(rel_diff));
+				}
+				jumps += 1;
+			}
+		}
+		println!("RelativeTime jumps: {}", jumps);
+
+	}
+
+	pub fn print_flags<P: StatPrinter>(&self) {
+
+		println!("\nFlags:");
+
+		println!("RD not set while Operational:");
+		for row in self.db.get_not_ready() {
+			println!("[{:>3}] {:<4} {:>8}/{:<8} ({:.2}%)", row.0, row.1, row.2, row.3, row.2 as f64 / row.3 as f64 * 100f64);
+		}
+
+		println!("Asynchronous request priorities (PRes with RS > 0):");
+		for row in self.db.get_request_priorities() {
+			println!("[{:>3}] PR={} {:>8}x (max RS={})", row.0, row.1, row.2, row.3);
+		}
+
+		println!("Exception handshakes (EN toggled until acknowledged by EA):");
+		self.print_field::<P>("EN/EA","exception_handshake","1==1","├─","└─","");
+
+	}
 
+	pub fn print_stats<P: StatPrinter>(&self) {
 		P::print_stats_header();
+		self.print_stats_since::<P>(&ReportMark::default());
+	}
+
+	/// Prints the stats tree over the SoCs and responses inserted after `mark`.
+	fn print_stats_since<P: StatPrinter>(&self, mark: &ReportMark) {
 
-		if let Ok(ref stats) = self.db.get_response_stats("soc", "1==1".to_owned()) {
+		if let Ok(ref stats) = self.db.get_response_stats("soc", format!("id > {}", mark.soc), self.db.get_resolution()) {
 			P::print_stats("Cycle/SoC",None,"",stats);
 		};
 
-		self.print_field::<P>("Responses","response","1==1","├─","├─","");
-		self.print_field::<P>("PRes","response","type=='pres'","│  ├─","│  └─","├─");
-		self.print_field::<P>("Ident","response","type=='ident'","│  ├─","│  └─","├─");
-		self.print_field::<P>("Status","response","type=='status'","│  ├─","│  └─","├─");
-		self.print_field::<P>("SDO","response","type=='sdo'","│  ├─","│  └─","├─");
-		self.print_field::<P>("NMT","response","type=='nmt_command'","│  ├─","│  └─","├─");
-		self.print_field::<P>("Veth","response","type=='veth'","   ├─","   └─","└─");
+		let phases = [("SoC-PReq","soc_preq"), ("Isochronous","isochronous"), ("PRes-SoA","pres_soa"), ("SoA-Async","soa_async"), ("Idle","idle")];
+		for (i, &(category, phase)) in phases.iter().enumerate() {
+			let prefix = if i+1 == phases.len() { "└─" } else { "├─" };
+			if let Ok(ref stats) = self.db.get_response_stats("cycle_phase", format!("id > {} AND phase=='{}'", mark.cycle_phase, phase), self.db.get_resolution()) {
+				P::print_stats(category,None,prefix,stats);
+			}
+		}
+
+		let since = format!("id > {}", mark.response);
+		self.print_field::<P>("Responses","response",&format!("{} AND type!='mn_turnaround'", since),"├─","├─","");
+		self.print_field::<P>("PRes","response",&format!("{} AND type=='pres'", since),"│  ├─","│  └─","├─");
+		self.print_field::<P>("Ident","response",&format!("{} AND type=='ident'", since),"│  ├─","│  └─","├─");
+		self.print_field::<P>("Status","response",&format!("{} AND type=='status'", since),"│  ├─","│  └─","├─");
+		self.print_field::<P>("SDO","response",&format!("{} AND type=='sdo'", since),"│  ├─","│  └─","├─");
+		self.print_field::<P>("NMT","response",&format!("{} AND type=='nmt_command'", since),"│  ├─","│  └─","├─");
+		self.print_field::<P>("Veth","response",&format!("{} AND type=='veth'", since),"   ├─","   └─","└─");
+		self.print_field::<P>("MN turnaround","response",&format!("{} AND type=='mn_turnaround'", since),"├─","└─","");
+
+	}
+
+	/// Returns the mark to pass to the next `print_report`.
+	pub fn mark(&self) -> ReportMark {
+		ReportMark {
+			soc: self.db.get_last_id("soc"),
+			cycle_phase: self.db.get_last_id("cycle_phase"),
+			response: self.db.get_last_id("response"),
+			errors: self.db.get_last_id("errors"),
+		}
+	}
+
+	/// Prints a live report with the stats and errors since `mark`.
+	/// `dropped` is the number of packets dropped by libpcap, if captured live.
+	pub fn print_report<P: StatPrinter>(&self, mark: &ReportMark, dropped: Option<u32>) {
+		print!("\n{} packets in {}s", self.db.get_total_num_packets(), self.db.get_total_time().num_milliseconds() as f64 / 1000f64);
+		match dropped {
+			Some(dropped) => println!(", {} dropped by libpcap", dropped),
+			None => println!(),
+		}
+		let errors = self.db.get_errors_since(mark.errors);
+		if !errors.is_empty() {
+			println!("New errors:");
+			let merged = self.db.get_files().len() > 1;
+			for group in errors {
+				self.print_error_group(&group, merged);
+			}
+		}
+		self.print_stats_since::<P>(mark);
+	}
+
+	/// Compares all captures stored in the database: the pooled stats of each category
+	/// followed by one row per capture.
+	pub fn print_aggregate<P: StatPrinter>(&self) {
+
+		P::print_aggregate_header();
+
+		let captures = self.db.get_captures();
+		let coarsest = captures.iter().map(|c| c.4).fold(Resolution::default(), |a, b| if b.nanoseconds() > a.nanoseconds() { b } else { a });
+
+		for &(category, table, condition) in AGGREGATE_CATEGORIES.iter() {
+
+			let pooled = match self.db.get_response_stats(table, condition.to_owned(), coarsest) {
+				Ok(stats) => stats,
+				Err(_) => continue,
+			};
+			P::print_aggregate(category, None, "", &pooled);
+
+			let rows: Vec<(&str, ResponseStats)> = captures.iter().filter_map(|c| {
+				self.db.get_response_stats(table, format!("capture_id=={} AND ({})", c.0, condition), c.4).ok().map(|stats| (&c.1[..], stats))
+			}).collect();
+
+			for (i, &(name, ref stats)) in rows.iter().enumerate() {
+				P::print_aggregate(category, Some(name), if i+1 == rows.len() { "└─" } else { "├─" }, stats);
+			}
+
+		}
+
+	}
+
+	pub fn print_sdo_transfers<P: StatPrinter>(&self) {
+
+		println!("\nSDO Transfers:");
+		for row in self.db.get_sdo_transfers() {
+			let object = match row.object {
+				Some((index, subindex)) => format!("0x{:04X}/0x{:02X}", index, subindex),
+				None => "-".to_owned(),
+			};
+			println!("{:>5} {:>14}ns [{:>3} -> {:>3}] {:<26} {:<13} {:>14}ns {:>5} cycles {:>8} bytes {:>3} retransmissions {}",
+				row.packet_id, Evaluation::group_digits(row.timestamp), row.client, row.server, row.command, object,
+				Evaluation::group_digits(row.duration_ns), row.cycles, row.bytes, row.retransmissions, row.result);
+		}
+
+		self.print_field::<P>("SDO transfer","sdo_transfer","result=='ok'","├─","└─","");
+
+	}
+
+	pub fn print_sdo_aborts<P: StatPrinter>(&self) {
+		println!("\nSDO Aborts:");
+		for row in self.db.get_sdo_aborts() {
+			let object = match row.1 {
+				Some((index, subindex)) => format!("0x{:04X}/0x{:02X}", index, subindex),
+				None => "unknown object".to_owned(),
+			};
+			println!("[{:>3}] {:>3}x {:<14} 0x{:08X} {}", row.0, row.3, object, row.2, abort_text(row.2));
+		}
+	}
+
+	pub fn print_nmt_commands<P: StatPrinter>(&self) {
+
+		println!("\nNMT Commands:");
+		for row in self.db.get_nmt_commands() {
+			let reaction = match (row.delay_ns, row.state) {
+				(Some(delay), Some(state)) => format!("-> {:?} after {}ns", state, Evaluation::group_digits(delay)),
+				(Some(delay), None) => format!("-> unknown state after {}ns", Evaluation::group_digits(delay)),
+				_ => "no state change observed".to_owned(),
+			};
+			println!("{:>5} {:>14}ns [{:>3}] {:<28} {}", row.packet_id, Evaluation::group_digits(row.timestamp), row.node_id, row.command, reaction);
+		}
+
+		self.print_field::<P>("NMT command","nmt_commands","timediff_ns IS NOT NULL","├─","└─","");
+
+	}
+
+	pub fn print_status<P: StatPrinter>(&self) {
+
+		println!("\nStatic Error Bit Fields:");
+		for row in self.db.get_static_errors() {
+			let bits: Vec<String> = row.1.iter().map(|b| format!("{:02X}", b)).collect();
+			println!("{:>5} {:>14}ns [{:>3}] error_register=0x{:02X} bits={}", row.3, Evaluation::group_digits(row.2), row.0, row.1[0], bits.join(" "));
+		}
+
+		println!("\nError/Event History:");
+		for (node, entry, timestamp, packet_id) in self.db.get_error_entries() {
+			let tm = time::at_utc(entry.timestamp);
+			println!("{:>5} {:>14}ns [{:>3}] {:<7} {:<7} profile=0x{:03X} code=0x{:04X} at {}.{:09} info=0x{:016X}",
+				packet_id, Evaluation::group_digits(timestamp), node,
+				if entry.is_status() { "status" } else { "history" }, entry.mode(), entry.profile(), entry.error_code,
+				tm.strftime("%Y-%m-%d %H:%M:%S").unwrap(), entry.timestamp.nsec, entry.additional_info);
+		}
 
 	}
 
 	fn print_field<P: StatPrinter>(&self, title: &str, table: &str, where_clause: &str, prefix: &str, prefix_end: &str, prefix_title: &str) {
 		
-		if let Ok(ref stats) = self.db.get_response_stats(table, where_clause.to_owned()) {
+		if let Ok(ref stats) = self.db.get_response_stats(table, where_clause.to_owned(), self.db.get_resolution()) {
 			P::print_stats(title, None, prefix_title, stats);
 		};
 
 		let nodes = self.db.get_nodes(table, where_clause.to_owned());
 
 		for (i,node) in nodes.iter().enumerate() {
-			if let Ok(ref stats) = self.db.get_response_stats(table, format!("{} AND node_id=={}",where_clause,node)) {
+			if let Ok(ref stats) = self.db.get_response_stats(table, format!("{} AND node_id=={}",where_clause,node), self.db.get_resolution()) {
 				let p = if i==nodes.len()-1 {
 					prefix_end
 				} else {
@@ -144,10 +522,10 @@ impl<'a> Evaluation<'a> {
 		println!("% {}", file_name);
 		println!("\\pgfplotstableread{{");
 		println!("x             y      y-min      y-max");
-		if let Ok(stats) = self.db.get_response_stats("response", format!("type=='sdo' AND node_id==240")) {
+		if let Ok(stats) = self.db.get_response_stats("response", format!("type=='sdo' AND node_id==240"), self.db.get_resolution()) {
 			println!("sdo   {:>9}  {:>9}  {:>9}", stats.avg as u64, stats.min, stats.max);
 		};
-		if let Ok(stats) = self.db.get_response_stats("response", format!("type=='nmt_command' AND node_id==240")) {
+		if let Ok(stats) = self.db.get_response_stats("response", format!("type=='nmt_command' AND node_id==240"), self.db.get_resolution()) {
 			println!("nmt   {:>9}  {:>9}  {:>9}", stats.avg as u64, stats.min, stats.max);
 		};
 		println!("preq  {:>9}  {:>9}  {:>9}", 0u64, 0u64, 0u64);
@@ -157,11 +535,45 @@ impl<'a> Evaluation<'a> {
 	pub fn print_raw(&self, filter: &str,sort: bool) {
 		let rows = self.db.get_raw(filter, sort);
 		for row in rows.iter() {
-			println!("{},{},{}", row.0, row.1, row.2);
+			let origin = &row.origin;
+			println!("{},{},{},{},{},{},{}", row.timediff_ns, row.packet_type, row.node_id,
+				origin.timestamp.num_nanoseconds().unwrap(), origin.cycle,
+				origin.request_packet_id.map(|id| id.to_string()).unwrap_or_default(), origin.packet_id);
+		}
+	}
+
+	/// Least squares slope of (capture time, MN time) samples expressed as deviation in ppm.
+	/// There is no slope if all samples have the same capture time.
+	fn drift_ppm(samples: &[(f64,f64)]) -> Option<f64> {
+		let n = samples.len() as f64;
+		let mean_x = samples.iter().map(|s| s.0).sum::<f64>() / n;
+		let mean_y = samples.iter().map(|s| s.1).sum::<f64>() / n;
+		let cov: f64 = samples.iter().map(|s| (s.0-mean_x)*(s.1-mean_y)).sum();
+		let var: f64 = samples.iter().map(|s| (s.0-mean_x)*(s.0-mean_x)).sum();
+		if var == 0f64 {
+			return None;
+		}
+		Some((cov / var - 1f64) * 1e6)
+	}
+
+	fn format_ppm(ppm: Option<f64>) -> String {
+		match ppm {
+			Some(ppm) => format!("{:>10.3}ppm", ppm),
+			None => format!("{:>10}", "n/a"),
+		}
+	}
+
+	pub fn print_pdo_values(&self) {
+		println!("cycle,timestamp,node,type,index,subindex,value");
+		for row in self.db.get_pdo_values() {
+			println!("{},{},{},{},0x{:04X},0x{:02X},{}", row.0, row.1, row.2, row.3, row.4, row.5, row.6);
 		}
 	}
 
 	fn group_digits(n: i64) -> String {
+		if n < 0 {
+			return format!("-{}", Evaluation::group_digits(n.unsigned_abs() as i64));
+		}
 		let string = n.to_string();
 		let bytes: Vec<_> = string.bytes().rev().collect();
 		let chunks: Vec<_> = bytes.chunks(3).map(|chunk| String::from_utf8_lossy(chunk)).collect();
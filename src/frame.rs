@@ -0,0 +1,647 @@
+// Powerlink Analyzer - Analyze Ethernet POWERLINK Network Traffic
+// Copyright (C) 2016, Thomas Keh
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, version 3 of the License.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The Frame module decodes raw Ethernet frames into typed POWERLINK frames.
+
+#![allow(dead_code)]
+
+use types::*;
+use num::FromPrimitive;
+use time::Timespec;
+
+/// EtherType of Ethernet POWERLINK frames.
+pub const ETHERTYPE_POWERLINK: u16 = 0x88AB;
+
+/// Tag protocol identifiers of 802.1Q and 802.1ad (QinQ) VLAN tags.
+const TPID_8021Q: u16 = 0x8100;
+const TPID_8021AD: u16 = 0x88A8;
+const TPID_QINQ_LEGACY: u16 = 0x9100;
+
+const VLAN_TAG_LEN: usize = 4;
+
+/// Minimum lengths of the POWERLINK part of each frame type.
+const SOC_LEN: usize = 22;
+const PREQ_LEN: usize = 10;
+const PRES_LEN: usize = 10;
+const SOA_LEN: usize = 9;
+const ASND_LEN: usize = 4;
+
+/// Link-layer header types of capture files we can analyze (see http://www.tcpdump.org/linktypes.html).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LinkType {
+	Ethernet,
+	/// Linux cooked capture as created by `tcpdump -i any`
+	LinuxSll,
+	/// Linux cooked capture v2
+	LinuxSll2,
+}
+
+impl LinkType {
+
+	pub fn from_dlt(dlt: i32) -> Option<LinkType> {
+		match dlt {
+			1 => Some(LinkType::Ethernet),
+			113 => Some(LinkType::LinuxSll),
+			276 => Some(LinkType::LinuxSll2),
+			_ => None,
+		}
+	}
+
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LinkHeader {
+	/// EtherType of the payload after all VLAN tags.
+	pub ethertype: u16,
+	/// ID of the innermost VLAN tag.
+	pub vlan_id: Option<u16>,
+	/// ID of the outer (service) tag of a QinQ frame.
+	pub outer_vlan_id: Option<u16>,
+	/// Length of the header including all VLAN tags.
+	pub len: usize,
+}
+
+impl LinkHeader {
+
+	/// Parses the link-layer header and any number of VLAN tags.
+	pub fn parse(link_type: LinkType, data: &[u8]) -> Option<LinkHeader> {
+		// Offset of the EtherType and length of the header without VLAN tags.
+		let (type_offset, len) = match link_type {
+			LinkType::Ethernet => (12, 14),
+			LinkType::LinuxSll => (14, 16),
+			LinkType::LinuxSll2 => (0, 20),
+		};
+		if data.len() < len {
+			return None;
+		}
+		LinkHeader::parse_tags(data, read_u16_be(data, type_offset), len)
+	}
+
+	/// Parses the VLAN tags following a header of length `len` with EtherType `ethertype`.
+	fn parse_tags(data: &[u8], ethertype: u16, len: usize) -> Option<LinkHeader> {
+
+		let mut header = LinkHeader {
+			ethertype,
+			vlan_id: None,
+			outer_vlan_id: None,
+			len,
+		};
+
+		while header.ethertype == TPID_8021Q || header.ethertype == TPID_8021AD || header.ethertype == TPID_QINQ_LEGACY {
+			if data.len() < header.len + VLAN_TAG_LEN {
+				return None;
+			}
+			if header.vlan_id.is_some() {
+				header.outer_vlan_id = header.outer_vlan_id.or(header.vlan_id);
+			}
+			header.vlan_id = Some(read_u16_be(data, header.len) & 0x0FFF);
+			header.ethertype = read_u16_be(data, header.len + 2);
+			header.len += VLAN_TAG_LEN;
+		}
+
+		Some(header)
+	}
+
+	pub fn is_powerlink(&self) -> bool {
+		self.ethertype == ETHERTYPE_POWERLINK
+	}
+
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SoC {
+	pub dst: u8,
+	pub src: u8,
+	pub flags1: u8,
+	pub flags2: u8,
+	/// Absolute time of the MN (IEEE 1588 seconds and nanoseconds).
+	pub net_time: Timespec,
+	/// Time since the MN started cycling in microseconds.
+	pub relative_time: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PReq<'a> {
+	pub dst: u8,
+	pub src: u8,
+	pub flags1: u8,
+	pub flags2: u8,
+	pub pdo_version: u8,
+	pub size: u16,
+	pub payload: &'a [u8],
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PRes<'a> {
+	pub dst: u8,
+	pub src: u8,
+	pub nmt_state: Option<NmtState>,
+	pub flags1: u8,
+	pub flags2: u8,
+	pub pdo_version: u8,
+	pub size: u16,
+	pub payload: &'a [u8],
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SoA {
+	pub dst: u8,
+	pub src: u8,
+	pub nmt_state: Option<NmtState>,
+	pub flags1: u8,
+	pub flags2: u8,
+	pub service_id: u8,
+	pub target: u8,
+	pub epl_version: u8,
+}
+
+impl SoA {
+	/// The service the MN invites `target` to.
+	pub fn service(&self) -> Option<ServiceId> {
+		ServiceId::from_u8(self.service_id)
+	}
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ASnd<'a> {
+	pub dst: u8,
+	pub src: u8,
+	pub service_id: u8,
+	pub payload: &'a [u8],
+}
+
+impl<'a> ASnd<'a> {
+	pub fn service(&self) -> Option<ServiceId> {
+		ServiceId::from_u8(self.service_id)
+	}
+}
+
+/// Decoded flag bits of a frame. Flags which do not exist in a frame type are false.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Flags {
+	/// Multiplexed cycle completed (SoC)
+	pub mc: bool,
+	/// Prescaled slot (SoC)
+	pub ps: bool,
+	/// Multiplexed slot (PReq, PRes)
+	pub ms: bool,
+	/// Exception acknowledge (PReq, SoA)
+	pub ea: bool,
+	/// Exception reset (SoA)
+	pub er: bool,
+	/// Exception new (PRes)
+	pub en: bool,
+	/// Ready, i.e. the PDO payload is valid (PReq, PRes)
+	pub rd: bool,
+	/// Priority of the pending asynchronous request (PRes)
+	pub pr: u8,
+	/// Number of pending asynchronous requests (PRes)
+	pub rs: u8,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Frame<'a> {
+	SoC(SoC),
+	PReq(PReq<'a>),
+	PRes(PRes<'a>),
+	SoA(SoA),
+	ASnd(ASnd<'a>),
+}
+
+impl<'a> Frame<'a> {
+
+	/// Decodes the POWERLINK part of a frame. Returns `None` if the message type is
+	/// unknown or the frame is too short for its type.
+	pub fn parse(data: &'a [u8]) -> Option<Frame<'a>> {
+
+		if data.len() < 3 {
+			return None;
+		}
+
+		let dst = data[1];
+		let src = data[2];
+
+		match PacketType::from_u8(data[0] & 0x7F) {
+
+			Some(PacketType::SoC) if data.len() >= SOC_LEN => {
+				Some(Frame::SoC(SoC {
+					dst,
+					src,
+					flags1: data[4],
+					flags2: data[5],
+					net_time: Timespec {
+						sec: read_u32_le(data, 6) as i64,
+						nsec: read_u32_le(data, 10) as i32,
+					},
+					relative_time: read_u64_le(data, 14),
+				}))
+			},
+
+			Some(PacketType::PReq) if data.len() >= PREQ_LEN => {
+				let size = read_u16_le(data, 8);
+				Some(Frame::PReq(PReq {
+					dst,
+					src,
+					flags1: data[4],
+					flags2: data[5],
+					pdo_version: data[6],
+					size,
+					payload: payload(data, PREQ_LEN, size),
+				}))
+			},
+
+			Some(PacketType::PRes) if data.len() >= PRES_LEN => {
+				let size = read_u16_le(data, 8);
+				Some(Frame::PRes(PRes {
+					dst,
+					src,
+					nmt_state: NmtState::from_u8(data[3]),
+					flags1: data[4],
+					flags2: data[5],
+					pdo_version: data[6],
+					size,
+					payload: payload(data, PRES_LEN, size),
+				}))
+			},
+
+			Some(PacketType::SoA) if data.len() >= SOA_LEN => {
+				Some(Frame::SoA(SoA {
+					dst,
+					src,
+					nmt_state: NmtState::from_u8(data[3]),
+					flags1: data[4],
+					flags2: data[5],
+					service_id: data[6],
+					target: data[7],
+					epl_version: data[8],
+				}))
+			},
+
+			Some(PacketType::ASnd) if data.len() >= ASND_LEN => {
+				Some(Frame::ASnd(ASnd {
+					dst,
+					src,
+					service_id: data[3],
+					payload: &data[ASND_LEN..],
+				}))
+			},
+
+			_ => None
+
+		}
+
+	}
+
+	pub fn packet_type(&self) -> PacketType {
+		match *self {
+			Frame::SoC(_) => PacketType::SoC,
+			Frame::PReq(_) => PacketType::PReq,
+			Frame::PRes(_) => PacketType::PRes,
+			Frame::SoA(_) => PacketType::SoA,
+			Frame::ASnd(_) => PacketType::ASnd,
+		}
+	}
+
+	pub fn flags(&self) -> Flags {
+		match *self {
+			Frame::SoC(ref f) => Flags {
+				mc: f.flags1 & 0x80 != 0,
+				ps: f.flags1 & 0x40 != 0,
+				..Flags::default()
+			},
+			Frame::PReq(ref f) => Flags {
+				ms: f.flags1 & 0x20 != 0,
+				ea: f.flags1 & 0x04 != 0,
+				rd: f.flags1 & 0x01 != 0,
+				..Flags::default()
+			},
+			Frame::PRes(ref f) => Flags {
+				ms: f.flags1 & 0x20 != 0,
+				en: f.flags1 & 0x10 != 0,
+				rd: f.flags1 & 0x01 != 0,
+				pr: (f.flags2 >> 3) & 0x07,
+				rs: f.flags2 & 0x07,
+				..Flags::default()
+			},
+			Frame::SoA(ref f) => Flags {
+				ea: f.flags1 & 0x04 != 0,
+				er: f.flags1 & 0x02 != 0,
+				..Flags::default()
+			},
+			Frame::ASnd(_) => Flags::default(),
+		}
+	}
+
+	/// Whether the EA flag of this frame is an acknowledge to the addressed CN, i.e.
+	/// the frame is a PReq or a SoA inviting a StatusResponse.
+	pub fn acknowledges_exception(&self) -> bool {
+		match *self {
+			Frame::PReq(_) => true,
+			Frame::SoA(ref f) => f.service() == Some(ServiceId::Status),
+			_ => false,
+		}
+	}
+
+	pub fn dst(&self) -> u8 {
+		match *self {
+			Frame::SoC(ref f) => f.dst,
+			Frame::PReq(ref f) => f.dst,
+			Frame::PRes(ref f) => f.dst,
+			Frame::SoA(ref f) => f.dst,
+			Frame::ASnd(ref f) => f.dst,
+		}
+	}
+
+	pub fn src(&self) -> u8 {
+		match *self {
+			Frame::SoC(ref f) => f.src,
+			Frame::PReq(ref f) => f.src,
+			Frame::PRes(ref f) => f.src,
+			Frame::SoA(ref f) => f.src,
+			Frame::ASnd(ref f) => f.src,
+		}
+	}
+
+}
+
+/// Returns the PDO payload of a PReq/PRes. Truncated captures yield a shorter slice.
+fn payload(data: &[u8], offset: usize, size: u16) -> &[u8] {
+	let end = ::std::cmp::min(data.len(), offset + size as usize);
+	&data[offset..end]
+}
+
+pub fn read_u16_be(data: &[u8], offset: usize) -> u16 {
+	(data[offset] as u16) << 8 | data[offset+1] as u16
+}
+
+pub fn read_u16_le(data: &[u8], offset: usize) -> u16 {
+	data[offset] as u16 | (data[offset+1] as u16) << 8
+}
+
+pub fn read_u32_le(data: &[u8], offset: usize) -> u32 {
+	read_u16_le(data, offset) as u32 | (read_u16_le(data, offset+2) as u32) << 16
+}
+
+pub fn read_u64_le(data: &[u8], offset: usize) -> u64 {
+	read_u32_le(data, offset) as u64 | (read_u32_le(data, offset+4) as u64) << 32
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+
+	fn soc() -> Vec<u8> {
+		let mut data = vec![PacketType::SoC as u8, 255, 240, 0, 0x80, 0];
+		data.extend_from_slice(&1_462_104_000u32.to_le_bytes());
+		data.extend_from_slice(&123_456_789u32.to_le_bytes());
+		data.extend_from_slice(&4_000_000u64.to_le_bytes());
+		data
+	}
+
+	fn preq(dst: u8, flags1: u8, pdo: &[u8]) -> Vec<u8> {
+		let mut data = vec![PacketType::PReq as u8, dst, 240, 0, flags1, 0, 0x20, 0];
+		data.extend_from_slice(&(pdo.len() as u16).to_le_bytes());
+		data.extend_from_slice(pdo);
+		data
+	}
+
+	fn pres(src: u8, flags1: u8, flags2: u8, pdo: &[u8]) -> Vec<u8> {
+		let mut data = vec![PacketType::PRes as u8, 255, src, NmtState::Operational as u8, flags1, flags2, 0x20, 0];
+		data.extend_from_slice(&(pdo.len() as u16).to_le_bytes());
+		data.extend_from_slice(pdo);
+		data
+	}
+
+	#[test]
+	fn parse_soc() {
+		let data = soc();
+		let frame = Frame::parse(&data).unwrap();
+		assert_eq!(frame.packet_type(), PacketType::SoC);
+		assert_eq!((frame.dst(), frame.src()), (255, 240));
+		match frame {
+			Frame::SoC(soc) => {
+				assert_eq!(soc.net_time, Timespec::new(1_462_104_000, 123_456_789));
+				assert_eq!(soc.relative_time, 4_000_000);
+			},
+			_ => unreachable!(),
+		}
+		let flags = frame.flags();
+		assert!(flags.mc && !flags.ps);
+
+		assert!(Frame::parse(&data[..SOC_LEN - 1]).is_none());
+	}
+
+	#[test]
+	fn parse_preq() {
+		let data = preq(3, 0x25, &[1, 2, 3, 4]);
+		let frame = Frame::parse(&data).unwrap();
+		assert_eq!((frame.dst(), frame.src()), (3, 240));
+		match frame {
+			Frame::PReq(preq) => {
+				assert_eq!(preq.pdo_version, 0x20);
+				assert_eq!(preq.size, 4);
+				assert_eq!(preq.payload, &[1, 2, 3, 4]);
+			},
+			_ => unreachable!(),
+		}
+		let flags = frame.flags();
+		assert!(flags.ms && flags.ea && flags.rd && !flags.en);
+		assert!(frame.acknowledges_exception());
+
+		// A truncated capture yields the captured part of the payload.
+		match Frame::parse(&data[..PREQ_LEN + 2]).unwrap() {
+			Frame::PReq(preq) => assert_eq!((preq.size, preq.payload), (4, &[1u8, 2][..])),
+			_ => unreachable!(),
+		}
+		assert!(Frame::parse(&data[..PREQ_LEN - 1]).is_none());
+	}
+
+	#[test]
+	fn parse_pres() {
+		// Padding after the announced size is not part of the payload.
+		let mut data = pres(7, 0x11, (5 << 3) | 2, &[9, 8]);
+		data.extend_from_slice(&[0; 30]);
+		let frame = Frame::parse(&data).unwrap();
+		assert_eq!((frame.dst(), frame.src()), (255, 7));
+		match frame {
+			Frame::PRes(pres) => {
+				assert_eq!(pres.nmt_state, Some(NmtState::Operational));
+				assert_eq!(pres.size, 2);
+				assert_eq!(pres.payload, &[9, 8]);
+			},
+			_ => unreachable!(),
+		}
+		let flags = frame.flags();
+		assert!(flags.en && flags.rd && !flags.ms && !flags.ea);
+		assert_eq!((flags.pr, flags.rs), (5, 2));
+		assert!(!frame.acknowledges_exception());
+	}
+
+	#[test]
+	fn parse_soa() {
+		let data = [PacketType::SoA as u8, 255, 240, NmtState::Operational as u8, 0x06, 0, ServiceId::Status as u8, 12, 0x20];
+		let frame = Frame::parse(&data).unwrap();
+		match frame {
+			Frame::SoA(soa) => {
+				assert_eq!(soa.service(), Some(ServiceId::Status));
+				assert_eq!(soa.target, 12);
+				assert_eq!(soa.epl_version, 0x20);
+			},
+			_ => unreachable!(),
+		}
+		let flags = frame.flags();
+		assert!(flags.ea && flags.er);
+		assert!(frame.acknowledges_exception());
+
+		let data = [PacketType::SoA as u8, 255, 240, 0, 0, 0, ServiceId::Ident as u8, 12, 0x20];
+		assert!(!Frame::parse(&data).unwrap().acknowledges_exception());
+		assert!(Frame::parse(&data[..SOA_LEN - 1]).is_none());
+	}
+
+	#[test]
+	fn parse_asnd() {
+		let data = [PacketType::ASnd as u8, 240, 5, ServiceId::Sdo as u8, 0xAA, 0xBB];
+		match Frame::parse(&data).unwrap() {
+			Frame::ASnd(asnd) => {
+				assert_eq!((asnd.dst, asnd.src), (240, 5));
+				assert_eq!(asnd.service(), Some(ServiceId::Sdo));
+				assert_eq!(asnd.payload, &[0xAA, 0xBB]);
+			},
+			_ => unreachable!(),
+		}
+		assert!(Frame::parse(&data[..ASND_LEN - 1]).is_none());
+	}
+
+	#[test]
+	fn parse_unknown_message_type() {
+		// The reserved bit of the message type is ignored.
+		let mut data = soc();
+		data[0] |= 0x80;
+		assert_eq!(Frame::parse(&data).unwrap().packet_type(), PacketType::SoC);
+		data[0] = 0x02;
+		assert!(Frame::parse(&data).is_none());
+		assert!(Frame::parse(&[]).is_none());
+	}
+
+	fn ethernet(ethertype: u16, tags: &[(u16, u16)]) -> Vec<u8> {
+		let mut data = vec![0xFF; 12];
+		for &(tpid, tci) in tags {
+			data.extend_from_slice(&tpid.to_be_bytes());
+			data.extend_from_slice(&tci.to_be_bytes());
+		}
+		data.extend_from_slice(&ethertype.to_be_bytes());
+		data.extend_from_slice(&[PacketType::SoA as u8, 255, 240]);
+		data
+	}
+
+	#[test]
+	fn parse_ethernet_header() {
+		let data = ethernet(ETHERTYPE_POWERLINK, &[]);
+		let header = LinkHeader::parse(LinkType::Ethernet, &data).unwrap();
+		assert!(header.is_powerlink());
+		assert_eq!(header.len, 14);
+		assert_eq!((header.vlan_id, header.outer_vlan_id), (None, None));
+
+		let header = LinkHeader::parse(LinkType::Ethernet, &ethernet(0x0800, &[])).unwrap();
+		assert!(!header.is_powerlink());
+	}
+
+	#[test]
+	fn parse_vlan_tag() {
+		// Priority bits are not part of the VLAN ID.
+		let data = ethernet(ETHERTYPE_POWERLINK, &[(TPID_8021Q, 0xA00A)]);
+		let header = LinkHeader::parse(LinkType::Ethernet, &data).unwrap();
+		assert!(header.is_powerlink());
+		assert_eq!(header.len, 18);
+		assert_eq!((header.vlan_id, header.outer_vlan_id), (Some(10), None));
+		assert_eq!(data[header.len], PacketType::SoA as u8);
+	}
+
+	#[test]
+	fn parse_qinq_tags() {
+		for &tpid in &[TPID_8021AD, TPID_QINQ_LEGACY] {
+			let data = ethernet(ETHERTYPE_POWERLINK, &[(tpid, 100), (TPID_8021Q, 20)]);
+			let header = LinkHeader::parse(LinkType::Ethernet, &data).unwrap();
+			assert!(header.is_powerlink());
+			assert_eq!(header.len, 22);
+			assert_eq!((header.vlan_id, header.outer_vlan_id), (Some(20), Some(100)));
+		}
+	}
+
+	#[test]
+	fn parse_truncated_ethernet_header() {
+		let data = ethernet(ETHERTYPE_POWERLINK, &[]);
+		assert!(LinkHeader::parse(LinkType::Ethernet, &data[..13]).is_none());
+		assert!(LinkHeader::parse(LinkType::Ethernet, &[]).is_none());
+		// The tag protocol ID announces a tag that is cut off.
+		let data = ethernet(ETHERTYPE_POWERLINK, &[(TPID_8021Q, 10)]);
+		assert!(LinkHeader::parse(LinkType::Ethernet, &data[..16]).is_none());
+	}
+
+	/// Builds a Linux cooked capture header of the given version for a POWERLINK frame.
+	fn cooked(link_type: LinkType, protocol: u16) -> Vec<u8> {
+		let mut data = match link_type {
+			LinkType::LinuxSll => {
+				// Packet type, ARPHRD type, address length and 8 address bytes
+				let mut data = vec![0, 4, 0, 1, 0, 6, 0, 0x60, 0x65, 0, 0, 0xF0, 0, 0];
+				data.extend_from_slice(&protocol.to_be_bytes());
+				data
+			},
+			LinkType::LinuxSll2 => {
+				let mut data = protocol.to_be_bytes().to_vec();
+				// Reserved, interface index, ARPHRD type, packet type, address length and address
+				data.extend_from_slice(&[0, 0, 0, 0, 0, 2, 0, 1, 4, 6, 0, 0x60, 0x65, 0, 0, 0xF0, 0, 0]);
+				data
+			},
+			LinkType::Ethernet => unreachable!(),
+		};
+		data.extend_from_slice(&[PacketType::SoA as u8, 255, 240]);
+		data
+	}
+
+	#[test]
+	fn parse_linux_sll_header() {
+		assert_eq!(LinkType::from_dlt(113), Some(LinkType::LinuxSll));
+		let data = cooked(LinkType::LinuxSll, ETHERTYPE_POWERLINK);
+		let header = LinkHeader::parse(LinkType::LinuxSll, &data).unwrap();
+		assert!(header.is_powerlink());
+		assert_eq!(header.len, 16);
+		assert_eq!(data[header.len], PacketType::SoA as u8);
+		assert!(LinkHeader::parse(LinkType::LinuxSll, &data[..15]).is_none());
+	}
+
+	#[test]
+	fn parse_linux_sll2_header() {
+		assert_eq!(LinkType::from_dlt(276), Some(LinkType::LinuxSll2));
+		let data = cooked(LinkType::LinuxSll2, ETHERTYPE_POWERLINK);
+		let header = LinkHeader::parse(LinkType::LinuxSll2, &data).unwrap();
+		assert!(header.is_powerlink());
+		assert_eq!(header.len, 20);
+		assert_eq!(data[header.len], PacketType::SoA as u8);
+		assert!(LinkHeader::parse(LinkType::LinuxSll2, &data[..19]).is_none());
+
+		let data = cooked(LinkType::LinuxSll2, 0x0800);
+		assert!(!LinkHeader::parse(LinkType::LinuxSll2, &data).unwrap().is_powerlink());
+	}
+
+	#[test]
+	fn unsupported_link_types() {
+		assert_eq!(LinkType::from_dlt(1), Some(LinkType::Ethernet));
+		assert_eq!(LinkType::from_dlt(0), None);
+		assert_eq!(LinkType::from_dlt(105), None);
+	}
+
+}
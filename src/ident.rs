@@ -0,0 +1,179 @@
+// Powerlink Analyzer - Analyze Ethernet POWERLINK Network Traffic
+// Copyright (C) 2016, Thomas Keh
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, version 3 of the License.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The Ident module decodes IdentResponse frames.
+
+use num::FromPrimitive;
+use time;
+use types::*;
+use frame::*;
+
+const IDENT_RESPONSE_LEN: usize = 110;
+
+/// Seconds between the Unix epoch and the POWERLINK epoch (1984-01-01).
+const EPOCH_1984: i64 = 441_763_200;
+
+#[derive(Debug, Clone, Default)]
+pub struct IdentResponse {
+	pub nmt_state: Option<NmtState>,
+	pub epl_version: u8,
+	pub feature_flags: u32,
+	pub mtu: u16,
+	pub poll_in_size: u16,
+	pub poll_out_size: u16,
+	/// Minimum PRes response time in nanoseconds.
+	pub response_time: u32,
+	pub device_type: u32,
+	pub vendor_id: u32,
+	pub product_code: u32,
+	pub revision_number: u32,
+	pub serial_number: u32,
+	/// Days since 1984-01-01.
+	pub verify_conf_date: u32,
+	/// Milliseconds since midnight.
+	pub verify_conf_time: u32,
+	pub ip_address: [u8; 4],
+	pub subnet_mask: [u8; 4],
+	pub default_gateway: [u8; 4],
+	pub host_name: String,
+}
+
+impl IdentResponse {
+
+	/// Decodes the payload of an ASnd IdentResponse frame.
+	pub fn parse(payload: &[u8]) -> Option<IdentResponse> {
+
+		if payload.len() < IDENT_RESPONSE_LEN {
+			return None;
+		}
+
+		// The host name is a zero terminated string of at most 32 characters.
+		let host_name = payload[78..110].split(|&b| b == 0).next().unwrap();
+
+		Some(IdentResponse {
+			nmt_state: NmtState::from_u8(payload[2]),
+			epl_version: payload[4],
+			feature_flags: read_u32_le(payload, 6),
+			mtu: read_u16_le(payload, 10),
+			poll_in_size: read_u16_le(payload, 12),
+			poll_out_size: read_u16_le(payload, 14),
+			response_time: read_u32_le(payload, 16),
+			device_type: read_u32_le(payload, 22),
+			vendor_id: read_u32_le(payload, 26),
+			product_code: read_u32_le(payload, 30),
+			revision_number: read_u32_le(payload, 34),
+			serial_number: read_u32_le(payload, 38),
+			verify_conf_date: read_u32_le(payload, 50),
+			verify_conf_time: read_u32_le(payload, 54),
+			// IP addresses are transmitted in network byte order.
+			ip_address: [payload[66], payload[67], payload[68], payload[69]],
+			subnet_mask: [payload[70], payload[71], payload[72], payload[73]],
+			default_gateway: [payload[74], payload[75], payload[76], payload[77]],
+			host_name: String::from_utf8_lossy(host_name).into_owned(),
+		})
+	}
+
+	pub fn epl_version_string(&self) -> String {
+		format!("{}.{}", self.epl_version >> 4, self.epl_version & 0x0F)
+	}
+
+	pub fn ip_address_string(&self) -> String {
+		format!("{}.{}.{}.{}", self.ip_address[0], self.ip_address[1], self.ip_address[2], self.ip_address[3])
+	}
+
+	/// Date and time of the last configuration as stored in object 0x1020.
+	pub fn verify_conf_string(&self) -> String {
+		let ts = time::Timespec::new(
+			EPOCH_1984 + self.verify_conf_date as i64 * 86_400 + (self.verify_conf_time / 1000) as i64,
+			(self.verify_conf_time % 1000) as i32 * 1_000_000);
+		match time::at_utc(ts).strftime("%Y-%m-%d %H:%M:%S") {
+			Ok(s) => s.to_string(),
+			Err(_) => String::new(),
+		}
+	}
+
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+
+	fn put_u32(payload: &mut [u8], offset: usize, value: u32) {
+		payload[offset..offset+4].copy_from_slice(&value.to_le_bytes());
+	}
+
+	fn ident_response() -> Vec<u8> {
+		let mut payload = vec![0u8; IDENT_RESPONSE_LEN];
+		payload[2] = NmtState::Operational as u8;
+		payload[4] = 0x20;
+		put_u32(&mut payload, 6, 0x0000_0245);
+		payload[10..12].copy_from_slice(&1500u16.to_le_bytes());
+		payload[12..14].copy_from_slice(&36u16.to_le_bytes());
+		payload[14..16].copy_from_slice(&40u16.to_le_bytes());
+		put_u32(&mut payload, 16, 25_000);
+		put_u32(&mut payload, 22, 0x000F_0191);
+		put_u32(&mut payload, 26, 0x0000_0100);
+		put_u32(&mut payload, 30, 0x0000_1234);
+		put_u32(&mut payload, 34, 0x0001_0002);
+		put_u32(&mut payload, 38, 987_654);
+		// 2016-05-01 12:34:56.789
+		put_u32(&mut payload, 50, 11_809);
+		put_u32(&mut payload, 54, 45_296_789);
+		payload[66..70].copy_from_slice(&[192, 168, 100, 1]);
+		payload[70..74].copy_from_slice(&[255, 255, 255, 0]);
+		payload[74..78].copy_from_slice(&[192, 168, 100, 254]);
+		payload[78..84].copy_from_slice(b"cn-001");
+		payload
+	}
+
+	#[test]
+	fn parse_ident_response() {
+		let ident = IdentResponse::parse(&ident_response()).unwrap();
+		assert_eq!(ident.nmt_state, Some(NmtState::Operational));
+		assert_eq!(ident.epl_version_string(), "2.0");
+		assert_eq!(ident.feature_flags, 0x245);
+		assert_eq!(ident.mtu, 1500);
+		assert_eq!((ident.poll_in_size, ident.poll_out_size), (36, 40));
+		assert_eq!(ident.response_time, 25_000);
+		assert_eq!(ident.device_type, 0x000F_0191);
+		assert_eq!(ident.vendor_id, 0x100);
+		assert_eq!(ident.product_code, 0x1234);
+		assert_eq!(ident.revision_number, 0x0001_0002);
+		assert_eq!(ident.serial_number, 987_654);
+		assert_eq!(ident.ip_address_string(), "192.168.100.1");
+		assert_eq!(ident.subnet_mask, [255, 255, 255, 0]);
+		assert_eq!(ident.default_gateway, [192, 168, 100, 254]);
+		assert_eq!(ident.host_name, "cn-001");
+		assert_eq!(ident.verify_conf_string(), "2016-05-01 12:34:56");
+	}
+
+	#[test]
+	fn parse_full_length_host_name() {
+		let mut payload = ident_response();
+		payload[78..110].copy_from_slice(&[b'h'; 32]);
+		// Trailing padding is not part of the host name.
+		payload.extend_from_slice(&[b'x'; 8]);
+		assert_eq!(IdentResponse::parse(&payload).unwrap().host_name, "h".repeat(32));
+	}
+
+	#[test]
+	fn reject_truncated_ident_response() {
+		let payload = ident_response();
+		assert!(IdentResponse::parse(&payload[..IDENT_RESPONSE_LEN - 1]).is_none());
+		assert!(IdentResponse::parse(&[]).is_none());
+	}
+
+}
@@ -0,0 +1,278 @@
+// Powerlink Analyzer - Analyze Ethernet POWERLINK Network Traffic
+// Copyright (C) 2016, Thomas Keh
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, version 3 of the License.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The Live module analyzes traffic from a network interface while it is captured, or from
+//! a capture file while it is written, and periodically reports the statistics of the last
+//! interval.
+
+use std::path::Path;
+use std::sync::atomic::{AtomicBool,Ordering};
+use std::time::{Duration,Instant};
+#[cfg(feature = "live")]
+use pcap::{self,Capture,PacketHeader,Precision};
+use libc;
+#[cfg(feature = "live")]
+use time::Timespec;
+use plkan::{Plkan,PlkanState};
+use database::{Database,FollowState};
+use evaluation::*;
+#[cfg(feature = "live")]
+use frame::LinkType;
+use pdo::PdoMapping;
+use window::Window;
+#[cfg(feature = "live")]
+use capture::Resolution;
+use capture::Source;
+
+/// libpcap returns at least this often, so that reports and interrupts are not delayed.
+#[cfg(feature = "live")]
+const READ_TIMEOUT_MS: i32 = 100;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_interrupt(_: libc::c_int) {
+	INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+fn catch_interrupt() {
+	unsafe {
+		libc::signal(libc::SIGINT, on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t);
+	}
+}
+
+/// Captures from `device` until interrupted with Ctrl-C. Every `interval` the stats and
+/// errors since the previous report are printed, at the end the overall results.
+#[cfg(feature = "live")]
+pub fn run(device: &str, interval: Duration, pdo_mapping: &PdoMapping, window: &Window) -> Result<(), String> {
+
+	let mut cap = Capture::from_device(device)
+		.and_then(|cap| cap.promisc(true).precision(Precision::Nano).timeout(READ_TIMEOUT_MS).open())
+		.map_err(|e| format!("Cannot capture on {}: {}", device, e))?;
+
+	let datalink = cap.get_datalink();
+	let link_type = LinkType::from_dlt(datalink.0)
+		.ok_or_else(|| format!("Unsupported link-layer type {} on {}.", datalink.0, device))?;
+
+	catch_interrupt();
+
+	let mut db = Database::new();
+	db.set_resolution(Resolution::Decimal(9));
+	info!("Capturing on {}. Press Ctrl-C to stop.", device);
+
+	{
+		let mut plkan = Plkan::new(&mut db);
+		plkan.set_pdo_mapping(pdo_mapping.clone());
+		plkan.set_window(window.clone());
+		let mut mark = ReportMark::default();
+		let mut last_report = Instant::now();
+
+		while !INTERRUPTED.load(Ordering::SeqCst) {
+
+			match cap.next() {
+				Ok(packet) => plkan.process_packet(link_type, timespec(packet.header), packet.data),
+				Err(pcap::Error::TimeoutExpired) => {},
+				Err(e) => return Err(format!("Capturing on {} failed: {}", device, e)),
+			}
+
+			if last_report.elapsed() >= interval {
+				let dropped = cap.stats().map(|s| s.dropped).unwrap_or(0);
+				let eval = Evaluation::new(plkan.database());
+				eval.print_report::<StdoutPrinter>(&mark, Some(dropped));
+				mark = eval.mark();
+				last_report = Instant::now();
+			}
+
+		}
+
+		plkan.finish();
+	}
+
+	print_summary(&db);
+
+	Ok(())
+}
+
+#[cfg(not(feature = "live"))]
+pub fn run(device: &str, _: Duration, _: &PdoMapping, _: &Window) -> Result<(), String> {
+	Err(format!("Cannot capture on {}: built without the live feature.", device))
+}
+
+/// Converts the timestamp of a packet read with `Precision::Nano` into a `Timespec`.
+#[cfg(feature = "live")]
+fn timespec(header: &PacketHeader) -> Timespec {
+	// With nanosecond precision libpcap stores nanoseconds in the tv_usec field.
+	Timespec {sec: header.ts.tv_sec, nsec: header.ts.tv_usec as i32}
+}
+
+/// Reads `path` while it is written, e.g. by dumpcap, until interrupted with Ctrl-C. Every
+/// `interval` the stats and errors since the previous report are printed.
+///
+/// With a database file the results are committed to it at every report. Following the
+/// same file into the same database again continues where the previous run stopped: the
+/// file is read on from the end of the last analyzed record with the protocol state at that
+/// point. Compressed files cannot be read from an offset, so their analyzed records are
+/// replayed instead, without storing their results again. SDO transfers still open at the
+/// stop are not stored.
+pub fn follow(path: &Path, db_path: Option<&Path>, interval: Duration, pdo_mapping: &PdoMapping, window: &Window) -> Result<(), String> {
+
+	let mut db = match db_path {
+		Some(db_path) => Database::open(db_path)?,
+		None => Database::new(),
+	};
+
+	let path_str = path.display().to_string();
+	let previous = match db.get_follow_state() {
+		Some(ref state) if state.path != path_str => return Err(format!("The database belongs to {}.", state.path)),
+		previous => previous,
+	};
+
+	catch_interrupt();
+
+	let resume = previous.as_ref().and_then(|state| state.resume.as_ref()).map(|&(offset, ref headers)| (offset, &headers[..]));
+	let mut source = Source::follow(path, &INTERRUPTED, resume).map_err(|e| format!("Cannot load {}: {}", path.display(), e))?;
+
+	let mut state = FollowState {
+		path: path_str.clone(),
+		records: 0,
+		resume: None,
+		packets: Vec::new(),
+		first_ts: None,
+		last_ts: None,
+		plkan: PlkanState::default(),
+	};
+	// Records up to this one only restore the protocol state.
+	let mut replayed = 0;
+	if let Some(previous) = previous {
+		if previous.resume.is_some() {
+			info!("Continuing after {} records analyzed already.", previous.records);
+			if let (Some(first_ts), Some(last_ts)) = (previous.plkan.first_ts, previous.last_ts) {
+				db.restore_counters(previous.plkan.packet_id, last_ts - first_ts);
+			}
+			state = previous;
+		} else {
+			info!("Replaying {} records analyzed already to restore the protocol state.", previous.records);
+			replayed = previous.records;
+		}
+	}
+	info!("Following {}. Press Ctrl-C to stop.", path.display());
+
+	let mut unsupported = Vec::new();
+	let mut checked = false;
+
+	{
+		let mut plkan = Plkan::new(&mut db);
+		plkan.set_pdo_mapping(pdo_mapping.clone());
+		plkan.set_window(window.clone());
+		plkan.restore(&state.plkan);
+		let mut mark = Evaluation::new(plkan.database()).mark();
+		let mut last_report = Instant::now();
+
+		loop {
+
+			let record = match source.next_record() {
+				Ok(Some(record)) => record,
+				Ok(None) => break,
+				Err(e) => {
+					// An interrupt in the middle of a record is no error, the record is read
+					// again by the next run.
+					if !INTERRUPTED.load(Ordering::SeqCst) {
+						warn!("Stopped reading {}: {}", path.display(), e);
+					}
+					break;
+				}
+			};
+
+			if !checked {
+				source.check_link_types(path)?;
+				checked = true;
+			}
+
+			state.records += 1;
+			plkan.set_replaying(state.records <= replayed);
+
+			if state.packets.len() <= record.interface {
+				state.packets.resize(record.interface + 1, 0);
+			}
+			state.packets[record.interface] += 1;
+			state.first_ts = state.first_ts.or(Some(record.ts));
+			state.last_ts = Some(record.ts);
+
+			match source.link_type(record.interface) {
+				Some(link_type) => {
+					plkan.process_packet(link_type, record.ts, &record.data);
+					if let Some(ref comment) = record.comment {
+						plkan.add_comment(record.interface, comment);
+					}
+				},
+				None => if !unsupported.contains(&record.interface) {
+					warn!("Skipping interface {} with unsupported link-layer type {}.", record.interface,
+						source.interfaces()[record.interface].link_type);
+					unsupported.push(record.interface);
+				},
+			}
+
+			if state.records > replayed && last_report.elapsed() >= interval {
+				state.resume = source.resume_point();
+				state.plkan = plkan.state();
+				let db = plkan.database();
+				if let Some(resolution) = source.resolution() {
+					db.set_resolution(resolution);
+				}
+				db.set_follow_state(&state);
+				db.commit();
+				let eval = Evaluation::new(db);
+				eval.print_report::<StdoutPrinter>(&mark, None);
+				mark = eval.mark();
+				last_report = Instant::now();
+			}
+
+		}
+
+		// With a database file the capture goes on in the next run, where the open SDO
+		// transfers may still complete, so they are not stored as incomplete here.
+		if db_path.is_none() {
+			plkan.finish();
+		}
+		state.resume = source.resume_point();
+		state.plkan = plkan.state();
+	}
+
+	db.set_follow_state(&state);
+	db.clear_file_info();
+	db.insert_file(0, &path_str, state.records, state.first_ts, state.last_ts);
+	for section in source.sections() {
+		db.insert_section(0, section);
+	}
+	for (id, interface) in source.interfaces().iter().enumerate() {
+		db.insert_interface(0, id, interface, state.packets.get(id).cloned().unwrap_or(0));
+	}
+	if let Some(resolution) = source.resolution() {
+		db.set_resolution(resolution);
+	}
+	db.insert_metadata(&path_str, state.first_ts);
+	db.commit();
+
+	print_summary(&db);
+
+	Ok(())
+}
+
+fn print_summary(db: &Database) {
+	let eval = Evaluation::new(db);
+	eval.print_metadata::<StdoutPrinter>();
+	eval.print_errors::<StdoutPrinter>();
+	eval.print_state_changes::<StdoutPrinter>();
+	eval.print_stats::<StdoutPrinter>();
+}
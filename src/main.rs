@@ -15,6 +15,7 @@
 
 //! The main module contains initialization tasks and user interaction.
 
+#[cfg(feature = "live")]
 extern crate pcap;
 extern crate time;
 #[macro_use] extern crate enum_primitive;
@@ -24,21 +25,43 @@ extern crate simplelog;
 extern crate rusqlite;
 extern crate getopts;
 extern crate regex;
+extern crate libc;
+extern crate flate2;
+extern crate ruzstd;
+extern crate xz2;
 
 mod plkan;
 mod types;
 mod database;
 mod evaluation;
+mod frame;
+mod pdo;
+mod sdo;
+mod nmt;
+mod ident;
+mod status;
+mod capture;
+mod pcapng;
+mod pcapfile;
+mod live;
+mod window;
+mod cycle;
 
-use pcap::*;
 use std::path::Path;
 use plkan::Plkan;
+use pdo::PdoMapping;
+use capture::{Source,Resolution};
+use window::{Window,TimeBound};
+use time::Timespec;
 use database::*;
 use evaluation::*;
 use getopts::Options;
 use std::env;
 use simplelog::{SimpleLogger,LogLevelFilter};
 use regex::Regex;
+use getopts::Matches;
+use std::cmp;
+use std::time::{Duration,Instant};
 
 fn print_usage(program: &str, opts: Options) {
 	let brief = format!("Usage: {} [options] PCAPNG_FILE", program);
@@ -56,9 +79,23 @@ fn main() {
 	opts.optflag("h", "help", "print this help menu");
 	opts.optflag("p", "pgftable", "prints master metrics as pgf table");
 	opts.optflag("c", "csv", "prints stats as csv");
-	opts.optflag("r", "raw", "prints raw response times as csv");
+	opts.optflag("r", "raw", "prints raw response times as csv (timediff, type, node, timestamp, cycle, request frame, response frame)");
 	opts.optflag("s", "sort", "sort response times (in combination with --raw)");
 	opts.optopt("f", "filter", "EXPERT: filter response times (in combination with --raw)", "SQL_WHERE_CLAUSE");
+	opts.optopt("m", "mapping", "extract PDO values using the given mapping file", "MAPPING_FILE");
+	opts.optflag("i", "inventory", "prints the device inventory as csv");
+	opts.optflag("d", "pdo-values", "prints extracted PDO values as csv (in combination with --mapping)");
+	opts.optflag("", "per-interface", "analyzes every interface of a pcapng file separately");
+	opts.optflag("", "merge", "merges all files in timestamp order into one analysis");
+	opts.optopt("l", "live", "captures from a network interface until interrupted", "INTERFACE");
+	opts.optopt("", "start", "ignores packets before this time in the stats", "TIME");
+	opts.optopt("", "end", "ignores packets after this time in the stats", "TIME");
+	opts.optopt("", "cycles", "only counts the given cycles in the stats", "FIRST:LAST");
+	opts.optflag("", "from-operational", "counts times and cycles from the first time all nodes are Operational");
+	opts.optflag("", "follow", "keeps reading a capture file while it grows until interrupted");
+	opts.optopt("o", "output", "stores the results in a new SQLite database file (with --follow: resumes a previous run)", "DB_FILE");
+	opts.optopt("", "interval", "seconds between live and follow reports (default 1)", "SECONDS");
+	opts.optflag("", "aggregate", "prints one report comparing all files (as csv in combination with --csv)");
 
 	let matches = match opts.parse(&args[1..]) {
 		Ok(m) => { m }
@@ -70,6 +107,70 @@ fn main() {
 		return;
 	}
 
+	let pdo_mapping = match matches.opt_str("m") {
+		Some(mapping_path) => match PdoMapping::from_file(Path::new(&mapping_path)) {
+			Ok(mapping) => mapping,
+			Err(e) => {
+				error!("{}", e);
+				return;
+			}
+		},
+		None => PdoMapping::default(),
+	};
+
+	let window = match parse_window(&matches) {
+		Ok(window) => window,
+		Err(e) => {
+			error!("{}", e);
+			return;
+		}
+	};
+
+	let interval = match matches.opt_str("interval").map(|s| s.parse::<f64>()) {
+		None => 1.0,
+		Some(Ok(seconds)) if seconds > 0.0 => seconds,
+		Some(_) => {
+			error!("Invalid interval, expected a positive number of seconds.");
+			return;
+		}
+	};
+	let interval = Duration::from_millis((interval * 1000.0) as u64);
+
+	if let Some(device) = matches.opt_str("l") {
+		if matches.opt_present("o") {
+			error!("--output cannot be combined with --live. Capture to a file and use --follow instead.");
+			return;
+		}
+		if let Err(e) = live::run(&device, interval, &pdo_mapping, &window) {
+			error!("{}", e);
+		}
+		return;
+	}
+
+	if matches.opt_present("follow") {
+		if matches.free.len() != 1 {
+			error!("--follow expects exactly one input file.");
+			return;
+		}
+		let db_path = matches.opt_str("o");
+		if let Err(e) = live::follow(Path::new(&matches.free[0]), db_path.as_ref().map(Path::new), interval, &pdo_mapping, &window) {
+			error!("{}", e);
+		}
+		return;
+	}
+
+	let output = matches.opt_str("o");
+	if output.is_some() {
+		if matches.opt_present("per-interface") {
+			error!("--output cannot be combined with --per-interface.");
+			return;
+		}
+		if matches.free.len() > 1 && !matches.opt_present("merge") && !matches.opt_present("aggregate") {
+			error!("--output stores a single capture. Use --merge or --aggregate for several files.");
+			return;
+		}
+	}
+
 	if matches.free.is_empty() {
 		error!("No input file given.");
 		//warn!("No input file given. Using example capture.");
@@ -83,39 +184,318 @@ fn main() {
 		"type=='pres'".to_string()
 	};
 
-	for file_path in &matches.free {
-		
-		//info!("Loading PCAP file {}.",file_path);
-		let file_path = Path::new(&file_path);
-		let mut cap = Capture::from_file_with_precision(file_path,Precision::Nano).expect("Loading PCAP file failed");
-		let mut db = Database::new();
-		
-		{
-			let mut plkan = Plkan::new(&mut db);
-			while let Ok(packet) = cap.next() {
-				plkan.process_packet(&packet);
+	let file_paths: Vec<&Path> = matches.free.iter().map(Path::new).collect();
+
+	// Without --merge, every file is analyzed on its own.
+	let groups: Vec<&[&Path]> = if matches.opt_present("merge") {
+		vec![&file_paths[..]]
+	} else {
+		file_paths.chunks(1).collect()
+	};
+
+	if matches.opt_present("aggregate") {
+
+		if matches.opt_present("per-interface") {
+			error!("--aggregate cannot be combined with --per-interface.");
+			return;
+		}
+
+		// All captures share one database, each stored under its own capture ID.
+		let mut db = match create_database(output.as_ref()) {
+			Ok(db) => db,
+			Err(e) => {
+				error!("{}", e);
+				return;
+			}
+		};
+		for file_paths in groups {
+			db.begin_capture(&file_paths[0].display().to_string());
+			if let Err(e) = analyze(&mut db, file_paths, None, &pdo_mapping, &window) {
+				error!("{}", e);
 			}
+			db.end_capture();
+		}
+		db.commit();
+
+		let eval = Evaluation::new(&db);
+		if matches.opt_present("c") {
+			eval.print_aggregate::<CsvPrinter>();
+		} else {
+			eval.print_aggregate::<StdoutPrinter>();
 		}
-		
-		let eval = Evaluation::new(&mut db);
-
-		if matches.opt_present("p") {
-			let filename = file_path.to_str().unwrap();
-			let table_name = file_path.file_stem().unwrap().to_str().unwrap();
-			let re = Regex::new(r"[0-9_]").unwrap();
-			let table_name = re.replace_all(table_name, "");
-			eval.print_pgftable(&filename, &table_name);
-		} else if matches.opt_present("c") {
-			eval.print_stats::<CsvPrinter>();
-		} else if matches.opt_present("r") {
-			eval.print_raw(&filter, matches.opt_present("s"));
+		return;
+	}
+
+	for file_paths in groups {
+
+		if matches.opt_present("per-interface") {
+			let mut interface_id = 0;
+			loop {
+				let mut db = Database::new();
+				match analyze(&mut db, file_paths, Some(interface_id), &pdo_mapping, &window) {
+					Ok(num_interfaces) => {
+						if interface_id < num_interfaces {
+							print_results(&matches, &filter, file_paths, Some(interface_id), &db);
+						}
+						interface_id += 1;
+						if interface_id >= num_interfaces {
+							break;
+						}
+					},
+					Err(e) => {
+						error!("{}", e);
+						break;
+					}
+				}
+			}
 		} else {
-			eval.print_metadata::<StdoutPrinter>();
-			eval.print_errors::<StdoutPrinter>();
-			eval.print_state_changes::<StdoutPrinter>();
-			eval.print_stats::<StdoutPrinter>();
+			let mut db = match create_database(output.as_ref()) {
+				Ok(db) => db,
+				Err(e) => {
+					error!("{}", e);
+					return;
+				}
+			};
+			match analyze(&mut db, file_paths, None, &pdo_mapping, &window) {
+				Ok(_) => {
+					db.commit();
+					print_results(&matches, &filter, file_paths, None, &db);
+				},
+				Err(e) => error!("{}", e),
+			}
+		}
+
+	}
+
+}
+
+/// Creates an in-memory database, or a database file with --output. Existing files are not
+/// overwritten, so that earlier results are not mixed up with new ones.
+fn create_database(output: Option<&String>) -> Result<Database, String> {
+	match output {
+		Some(path) if Path::new(path).exists() => Err(format!("{} exists already.", path)),
+		Some(path) => Database::open(Path::new(path)),
+		None => Ok(Database::new()),
+	}
+}
+
+/// Builds the analysis window from the command line.
+fn parse_window(matches: &Matches) -> Result<Window, String> {
+	let mut window = Window::default();
+	if let Some(start) = matches.opt_str("start") {
+		window.start = Some(TimeBound::parse(&start)?);
+	}
+	if let Some(end) = matches.opt_str("end") {
+		window.end = Some(TimeBound::parse(&end)?);
+	}
+	if let Some(cycles) = matches.opt_str("cycles") {
+		window.set_cycles(&cycles)?;
+	}
+	window.from_operational = matches.opt_present("from-operational");
+	Ok(window)
+}
+
+/// Minimum time between progress updates.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Per-file bookkeeping while reading.
+struct FileState {
+	source: Source,
+	next: Option<pcapng::Record>,
+	packets: Vec<usize>,
+	first_ts: Option<Timespec>,
+	last_ts: Option<Timespec>,
+	unsupported: Vec<usize>,
+}
+
+/// Reads the given files, either with all interfaces or only with the given one. Several
+/// files are merged in timestamp order. Returns the largest number of interfaces of a file.
+fn analyze(db: &mut Database, file_paths: &[&Path], interface_id: Option<usize>, pdo_mapping: &PdoMapping, window: &Window) -> Result<usize, String> {
+
+	let mut files = Vec::new();
+	for file_path in file_paths {
+		let mut source = Source::open(file_path).map_err(|e| format!("Cannot load {}: {}", file_path.display(), e))?;
+		let next = read_next(&mut source, file_path);
+		files.push(FileState {
+			source,
+			next,
+			packets: Vec::new(),
+			first_ts: None,
+			last_ts: None,
+			unsupported: Vec::new(),
+		});
+	}
+
+	// Interfaces with unsupported link-layer types are skipped as long as any interface can
+	// be analyzed. The interfaces before the first packet of each file are known already.
+	let checks: Vec<Result<(), String>> = files.iter()
+		.zip(file_paths)
+		.map(|(file, file_path)| file.source.check_link_types(file_path))
+		.collect();
+	if checks.iter().all(|c| c.is_err()) {
+		if let Some(Err(e)) = checks.into_iter().next() {
+			return Err(e);
+		}
+	}
+
+	{
+		let mut plkan = Plkan::new(db);
+		plkan.set_pdo_mapping(pdo_mapping.clone());
+		plkan.set_window(window.clone());
+
+		// Progress is only shown on a terminal, so that redirected output stays clean.
+		let show_progress = unsafe { libc::isatty(2) } == 1;
+		let mut last_progress = Instant::now();
+		let mut num_records = 0usize;
+
+		// The file with the earliest pending packet is next. Ties go to the earlier file.
+		while let Some((_, file_id)) = files.iter()
+			.enumerate()
+			.filter_map(|(id, file)| file.next.as_ref().map(|record| (record.ts, id)))
+			.min() {
+
+			num_records += 1;
+			if show_progress && num_records.is_multiple_of(1024) && last_progress.elapsed() >= PROGRESS_INTERVAL {
+				print_progress(&files, num_records);
+				last_progress = Instant::now();
+			}
+
+			let file = &mut files[file_id];
+			let record = file.next.take().unwrap();
+			file.next = read_next(&mut file.source, file_paths[file_id]);
+
+			if file.packets.len() <= record.interface {
+				file.packets.resize(record.interface + 1, 0);
+			}
+			file.packets[record.interface] += 1;
+			file.first_ts = file.first_ts.or(Some(record.ts));
+			file.last_ts = Some(record.ts);
+
+			if interface_id.is_some() && interface_id != Some(record.interface) {
+				continue;
+			}
+
+			let link_type = match file.source.link_type(record.interface) {
+				Some(link_type) => link_type,
+				None => {
+					if !file.unsupported.contains(&record.interface) {
+						warn!("Skipping interface {} of {} with unsupported link-layer type {}.", record.interface,
+							file_paths[file_id].display(), file.source.interfaces()[record.interface].link_type);
+						file.unsupported.push(record.interface);
+					}
+					continue;
+				}
+			};
+
+			plkan.set_file(file_id);
+			plkan.process_packet(link_type, record.ts, &record.data);
+			if let Some(ref comment) = record.comment {
+				plkan.add_comment(record.interface, comment);
+			}
+
+		}
+
+		plkan.finish();
+
+		if show_progress && num_records > 0 {
+			eprint!("\r{:60}\r", "");
+		}
+	}
+
+	let mut resolution: Option<Resolution> = None;
+	let mut num_interfaces = 0;
+
+	for (file_id, file) in files.iter().enumerate() {
+
+		db.insert_file(file_id, &file_paths[file_id].display().to_string(), file.packets.iter().sum(), file.first_ts, file.last_ts);
+
+		for section in file.source.sections() {
+			db.insert_section(file_id, section);
+		}
+
+		for (id, interface) in file.source.interfaces().iter().enumerate() {
+			if interface_id.is_none() || interface_id == Some(id) {
+				db.insert_interface(file_id, id, interface, file.packets.get(id).cloned().unwrap_or(0));
+				// Latencies are only as exact as the coarsest interface.
+				resolution = match resolution {
+					Some(r) if r.nanoseconds() >= interface.resolution.nanoseconds() => Some(r),
+					_ => Some(interface.resolution),
+				};
+			}
 		}
 
+		num_interfaces = cmp::max(num_interfaces, file.source.interfaces().len());
 	}
 
-}
\ No newline at end of file
+	if let Some(resolution) = resolution {
+		db.set_resolution(resolution);
+	}
+
+	let sources: Vec<String> = file_paths.iter().map(|p| p.display().to_string()).collect();
+	db.insert_metadata(&sources.join(", "), files.iter().filter_map(|f| f.first_ts).min());
+
+	Ok(num_interfaces)
+}
+
+/// Prints the bytes read from all files on stderr, relative to their total size if known.
+fn print_progress(files: &[FileState], num_records: usize) {
+	let read: u64 = files.iter().map(|f| f.source.progress().0).sum();
+	let total: Option<u64> = files.iter().map(|f| f.source.progress().1).sum();
+	let mb = |bytes: u64| bytes as f64 / 1e6;
+	match total {
+		Some(total) if total > 0 => eprint!("\r{:5.1}% ({:.1} of {:.1} MB), {} packets", read as f64 * 100.0 / total as f64, mb(read), mb(total), num_records),
+		_ => eprint!("\r{:.1} MB, {} packets", mb(read), num_records),
+	}
+}
+
+/// Reads the next packet of a file. Read errors end the file, so that truncated captures
+/// are still analyzed up to the error.
+fn read_next(source: &mut Source, file_path: &Path) -> Option<pcapng::Record> {
+	match source.next_record() {
+		Ok(record) => record,
+		Err(e) => {
+			warn!("Stopped reading {}: {}", file_path.display(), e);
+			None
+		}
+	}
+}
+
+fn print_results(matches: &Matches, filter: &str, file_paths: &[&Path], interface_id: Option<usize>, db: &Database) {
+
+	let eval = Evaluation::new(db);
+
+	// Merged files are named after the first one.
+	let file_path = file_paths[0];
+
+	if matches.opt_present("p") {
+		let filename = file_path.to_str().unwrap();
+		let table_name = file_path.file_stem().unwrap().to_str().unwrap();
+		let re = Regex::new(r"[0-9_]").unwrap();
+		let table_name = re.replace_all(table_name, "");
+		eval.print_pgftable(&filename, &table_name);
+	} else if matches.opt_present("c") {
+		eval.print_stats::<CsvPrinter>();
+	} else if matches.opt_present("i") {
+		eval.print_inventory::<CsvPrinter>();
+	} else if matches.opt_present("d") {
+		eval.print_pdo_values();
+	} else if matches.opt_present("r") {
+		eval.print_raw(filter, matches.opt_present("s"));
+	} else {
+		if let Some(interface_id) = interface_id {
+			println!("\n=== {} interface {} ===", file_path.display(), interface_id);
+		}
+		eval.print_metadata::<StdoutPrinter>();
+		eval.print_inventory::<StdoutPrinter>();
+		eval.print_errors::<StdoutPrinter>();
+		eval.print_state_changes::<StdoutPrinter>();
+		eval.print_stats::<StdoutPrinter>();
+		eval.print_sdo_transfers::<StdoutPrinter>();
+		eval.print_sdo_aborts::<StdoutPrinter>();
+		eval.print_nmt_commands::<StdoutPrinter>();
+		eval.print_status::<StdoutPrinter>();
+		eval.print_clock::<StdoutPrinter>();
+		eval.print_flags::<StdoutPrinter>();
+	}
+
+}
@@ -0,0 +1,120 @@
+// Powerlink Analyzer - Analyze Ethernet POWERLINK Network Traffic
+// Copyright (C) 2016, Thomas Keh
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, version 3 of the License.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The NMT module decodes NMT commands sent by the MN via ASnd.
+
+use num::FromPrimitive;
+use types::*;
+
+/// Destination of NMT commands which address all nodes.
+const BROADCAST: u8 = 255;
+
+#[derive(Debug, Clone, Copy)]
+pub struct NmtCommand<'a> {
+	pub command_id: u8,
+	pub data: &'a [u8],
+}
+
+impl<'a> NmtCommand<'a> {
+
+	/// Decodes the payload of an ASnd NMT command frame.
+	pub fn parse(payload: &'a [u8]) -> Option<NmtCommand<'a>> {
+		if payload.len() < 2 {
+			return None;
+		}
+		Some(NmtCommand {
+			command_id: payload[0],
+			data: &payload[2..],
+		})
+	}
+
+	pub fn command(&self) -> Option<NmtCommandId> {
+		NmtCommandId::from_u8(self.command_id)
+	}
+
+	/// Returns the nodes addressed by a command sent to `dst`. Extended commands carry a node
+	/// list bit field; plain broadcasts address all `known_nodes`.
+	pub fn targets(&self, dst: u8, known_nodes: &[u8]) -> Vec<u8> {
+		match self.command() {
+			Some(command) if command.is_extended() && dst == BROADCAST => {
+				let mut nodes = Vec::new();
+				for (byte, bits) in self.data.iter().take(32).enumerate() {
+					for bit in 0..8 {
+						if bits & (1 << bit) != 0 {
+							nodes.push((byte * 8 + bit) as u8);
+						}
+					}
+				}
+				nodes
+			},
+			_ if dst == BROADCAST => known_nodes.to_vec(),
+			_ => vec![dst],
+		}
+	}
+
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+
+	fn command(id: NmtCommandId, node_list: &[u8]) -> Vec<u8> {
+		let mut payload = vec![id as u8, 0];
+		payload.extend_from_slice(node_list);
+		payload
+	}
+
+	#[test]
+	fn parse() {
+		let payload = command(NmtCommandId::NmtStartNode, &[]);
+		let nmt = NmtCommand::parse(&payload).unwrap();
+		assert_eq!(nmt.command(), Some(NmtCommandId::NmtStartNode));
+		assert!(nmt.data.is_empty());
+		assert_eq!(NmtCommand::parse(&[0x4F, 0]).unwrap().command(), None);
+		assert!(NmtCommand::parse(&[0x21]).is_none());
+	}
+
+	#[test]
+	fn extended_command_targets_node_list() {
+		let mut node_list = [0u8; 32];
+		// Nodes 1, 2, 9, 240 and 254
+		node_list[0] = 0b0000_0110;
+		node_list[1] = 0b0000_0010;
+		node_list[30] = 0b0000_0001;
+		node_list[31] = 0b0100_0000;
+		let payload = command(NmtCommandId::NmtResetNodeEx, &node_list);
+		let nmt = NmtCommand::parse(&payload).unwrap();
+		assert_eq!(nmt.targets(BROADCAST, &[1, 3]), vec![1, 2, 9, 240, 254]);
+
+		// Bits after the 32 bytes of the node list are no nodes.
+		let mut payload = command(NmtCommandId::NmtStartNodeEx, &[0; 32]);
+		payload.push(0xFF);
+		assert!(NmtCommand::parse(&payload).unwrap().targets(BROADCAST, &[1, 3]).is_empty());
+	}
+
+	#[test]
+	fn plain_command_targets() {
+		let payload = command(NmtCommandId::NmtStartNode, &[]);
+		let nmt = NmtCommand::parse(&payload).unwrap();
+		assert_eq!(nmt.targets(BROADCAST, &[1, 3, 5]), vec![1, 3, 5]);
+		assert_eq!(nmt.targets(3, &[1, 3, 5]), vec![3]);
+
+		// An extended command sent to a single node addresses that node.
+		let payload = command(NmtCommandId::NmtStopNodeEx, &[0xFF; 32]);
+		assert_eq!(NmtCommand::parse(&payload).unwrap().targets(7, &[1, 3, 5]), vec![7]);
+	}
+
+}
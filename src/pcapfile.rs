@@ -0,0 +1,212 @@
+// Powerlink Analyzer - Analyze Ethernet POWERLINK Network Traffic
+// Copyright (C) 2016, Thomas Keh
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, version 3 of the License.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The pcapfile module reads classic pcap files from any reader, so that they can be
+//! decompressed or read from stdin on the fly.
+
+use std::io::{self,Read};
+use time::Timespec;
+use capture::Resolution;
+use pcapng::{Interface,Record};
+
+pub const MAGIC_MICRO: u32 = 0xA1B2_C3D4;
+pub const MAGIC_NANO: u32 = 0xA1B2_3C4D;
+
+/// Records larger than this are rejected instead of allocated.
+const MAX_RECORD_LEN: usize = 16 * 1024 * 1024;
+
+pub struct PcapReader<R> {
+	reader: R,
+	big_endian: bool,
+	/// A pcap file is described as a single interface.
+	interface: [Interface; 1],
+	header: [u8; 24],
+	/// Bytes of the header and all records read so far.
+	position: u64,
+}
+
+impl<R: Read> PcapReader<R> {
+
+	/// Reads the file header.
+	pub fn new(mut reader: R) -> io::Result<Self> {
+
+		let mut header = [0u8; 24];
+		reader.read_exact(&mut header)?;
+
+		let magic = [header[0], header[1], header[2], header[3]];
+		let (big_endian, resolution) = match (u32::from_le_bytes(magic), u32::from_be_bytes(magic)) {
+			(MAGIC_MICRO, _) => (false, Resolution::Decimal(6)),
+			(MAGIC_NANO, _) => (false, Resolution::Decimal(9)),
+			(_, MAGIC_MICRO) => (true, Resolution::Decimal(6)),
+			(_, MAGIC_NANO) => (true, Resolution::Decimal(9)),
+			_ => return Err(io::Error::new(io::ErrorKind::InvalidData, "not a pcap file")),
+		};
+
+		let mut pcap = PcapReader {
+			reader,
+			big_endian,
+			interface: [Interface {
+				link_type: 0,
+				name: None,
+				description: None,
+				resolution,
+				offset: 0,
+			}],
+			header,
+			position: header.len() as u64,
+		};
+		// The upper bits of the link-layer type field may carry FCS information.
+		pcap.interface[0].link_type = pcap.u32_at(&header, 20) as u16;
+
+		Ok(pcap)
+	}
+
+	pub fn interfaces(&self) -> &[Interface] {
+		&self.interface
+	}
+
+	/// Number of bytes up to the end of the last record read.
+	pub fn position(&self) -> u64 {
+		self.position
+	}
+
+	/// The file header. Followed by the records after `position`, it forms a valid file.
+	pub fn headers(&self) -> &[u8] {
+		&self.header
+	}
+
+	/// Returns the next packet or `None` at the end of the file.
+	pub fn next_record(&mut self) -> io::Result<Option<Record>> {
+
+		let mut header = [0u8; 16];
+		match self.reader.read_exact(&mut header) {
+			Ok(()) => {},
+			Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+			Err(e) => return Err(e),
+		}
+
+		let sec = self.u32_at(&header, 0) as i64;
+		let fraction = self.u32_at(&header, 4) as i64;
+		let captured_len = self.u32_at(&header, 8) as usize;
+		if captured_len > MAX_RECORD_LEN {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid record length"));
+		}
+
+		let mut data = vec![0u8; captured_len];
+		self.reader.read_exact(&mut data)?;
+		self.position += (header.len() + captured_len) as u64;
+
+		let nsec = match self.interface[0].resolution {
+			Resolution::Decimal(9) => fraction,
+			_ => fraction * 1000,
+		};
+
+		Ok(Some(Record {
+			interface: 0,
+			ts: Timespec {sec, nsec: nsec as i32},
+			data,
+			comment: None,
+		}))
+	}
+
+	fn u32_at(&self, data: &[u8], offset: usize) -> u32 {
+		let bytes = [data[offset], data[offset+1], data[offset+2], data[offset+3]];
+		if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) }
+	}
+
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use std::io::Cursor;
+
+	fn file(magic: u32, big_endian: bool, link_type: u32, records: &[(u32, u32, &[u8])]) -> Vec<u8> {
+		let u32_bytes = |value: u32| if big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+		let mut file = Vec::new();
+		file.extend_from_slice(&u32_bytes(magic));
+		file.extend_from_slice(&[0; 12]);
+		file.extend_from_slice(&u32_bytes(65535));
+		file.extend_from_slice(&u32_bytes(link_type));
+		for &(sec, fraction, data) in records {
+			file.extend_from_slice(&u32_bytes(sec));
+			file.extend_from_slice(&u32_bytes(fraction));
+			file.extend_from_slice(&u32_bytes(data.len() as u32));
+			file.extend_from_slice(&u32_bytes(data.len() as u32));
+			file.extend_from_slice(data);
+		}
+		file
+	}
+
+	#[test]
+	fn little_endian_microseconds() {
+		let data = file(MAGIC_MICRO, false, 1, &[(10, 250_000, &[1, 2, 3]), (11, 0, &[])]);
+		let mut reader = PcapReader::new(Cursor::new(data.clone())).unwrap();
+		assert_eq!(reader.interfaces()[0].link_type, 1);
+		assert_eq!(reader.interfaces()[0].resolution, Resolution::Decimal(6));
+
+		let record = reader.next_record().unwrap().unwrap();
+		assert_eq!(record.interface, 0);
+		assert_eq!(record.ts, Timespec::new(10, 250_000_000));
+		assert_eq!(record.data, vec![1, 2, 3]);
+		assert_eq!(reader.position(), 24 + 16 + 3);
+
+		let record = reader.next_record().unwrap().unwrap();
+		assert_eq!(record.ts, Timespec::new(11, 0));
+		assert!(record.data.is_empty());
+		assert!(reader.next_record().unwrap().is_none());
+		assert_eq!(reader.position(), data.len() as u64);
+	}
+
+	#[test]
+	fn big_endian_nanoseconds() {
+		// The upper bits of the link-layer type are not part of it.
+		let data = file(MAGIC_NANO, true, 0x1000_0071, &[(7, 123_456_789, &[0xAB])]);
+		let mut reader = PcapReader::new(Cursor::new(data)).unwrap();
+		assert_eq!(reader.interfaces()[0].link_type, 113);
+		assert_eq!(reader.interfaces()[0].resolution, Resolution::Decimal(9));
+
+		let record = reader.next_record().unwrap().unwrap();
+		assert_eq!(record.ts, Timespec::new(7, 123_456_789));
+		assert_eq!(record.data, vec![0xAB]);
+	}
+
+	#[test]
+	fn header_continues_at_position() {
+		let data = file(MAGIC_MICRO, false, 1, &[(1, 0, &[1]), (2, 0, &[2])]);
+		let mut reader = PcapReader::new(Cursor::new(data.clone())).unwrap();
+		reader.next_record().unwrap().unwrap();
+		let mut rest = reader.headers().to_vec();
+		rest.extend_from_slice(&data[reader.position() as usize..]);
+
+		let mut reader = PcapReader::new(Cursor::new(rest)).unwrap();
+		assert_eq!(reader.next_record().unwrap().unwrap().data, vec![2]);
+		assert!(reader.next_record().unwrap().is_none());
+	}
+
+	#[test]
+	fn invalid_files() {
+		assert!(PcapReader::new(Cursor::new(vec![0u8; 24])).is_err());
+		assert!(PcapReader::new(Cursor::new(vec![0u8; 10])).is_err());
+
+		// A record cut off in its data is an error, not the end of the file.
+		let mut data = file(MAGIC_MICRO, false, 1, &[(1, 0, &[1, 2, 3])]);
+		data.pop();
+		let mut reader = PcapReader::new(Cursor::new(data)).unwrap();
+		assert!(reader.next_record().is_err());
+	}
+
+}
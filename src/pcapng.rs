@@ -0,0 +1,515 @@
+// Powerlink Analyzer - Analyze Ethernet POWERLINK Network Traffic
+// Copyright (C) 2016, Thomas Keh
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, version 3 of the License.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The pcapng module reads pcapng files without libpcap, keeping the interface of every
+//! packet, per-interface timestamp resolution and offset, packet comments and the section
+//! header metadata.
+
+use std::io::{self,Read};
+use time::Timespec;
+use capture::Resolution;
+
+const BLOCK_SECTION_HEADER: u32 = 0x0A0D_0D0A;
+const BLOCK_INTERFACE_DESCRIPTION: u32 = 0x0000_0001;
+const BLOCK_PACKET: u32 = 0x0000_0002;
+const BLOCK_SIMPLE_PACKET: u32 = 0x0000_0003;
+const BLOCK_ENHANCED_PACKET: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+
+const OPT_END: u16 = 0;
+const OPT_COMMENT: u16 = 1;
+const OPT_SHB_HARDWARE: u16 = 2;
+const OPT_SHB_OS: u16 = 3;
+const OPT_SHB_USERAPPL: u16 = 4;
+const OPT_IF_NAME: u16 = 2;
+const OPT_IF_DESCRIPTION: u16 = 3;
+const OPT_IF_TSRESOL: u16 = 9;
+const OPT_IF_TSOFFSET: u16 = 14;
+
+/// Blocks larger than this are rejected instead of allocated.
+const MAX_BLOCK_LEN: usize = 16 * 1024 * 1024;
+
+/// Metadata of a section header block.
+#[derive(Debug, Clone, Default)]
+pub struct Section {
+	pub hardware: Option<String>,
+	pub os: Option<String>,
+	pub application: Option<String>,
+	pub comment: Option<String>,
+}
+
+/// An interface description block.
+#[derive(Debug, Clone)]
+pub struct Interface {
+	pub link_type: u16,
+	pub name: Option<String>,
+	pub description: Option<String>,
+	pub resolution: Resolution,
+	/// Seconds to add to every timestamp of this interface.
+	pub offset: i64,
+}
+
+/// A packet with its timestamp in nanoseconds and the index of its interface. Interface
+/// indices are counted across all sections of the file.
+#[derive(Debug)]
+pub struct Record {
+	pub interface: usize,
+	pub ts: Timespec,
+	pub data: Vec<u8>,
+	pub comment: Option<String>,
+}
+
+pub struct PcapngReader<R> {
+	reader: R,
+	big_endian: bool,
+	sections: Vec<Section>,
+	interfaces: Vec<Interface>,
+	/// Index of the first interface of the current section.
+	section_start: usize,
+	/// Bytes of all blocks read so far.
+	position: u64,
+	/// Raw section header and interface description blocks read so far.
+	headers: Vec<u8>,
+}
+
+impl<R: Read> PcapngReader<R> {
+
+	pub fn new(reader: R) -> Self {
+		PcapngReader {
+			reader,
+			big_endian: false,
+			sections: Vec::new(),
+			interfaces: Vec::new(),
+			section_start: 0,
+			position: 0,
+			headers: Vec::new(),
+		}
+	}
+
+	/// All sections read so far.
+	pub fn sections(&self) -> &[Section] {
+		&self.sections
+	}
+
+	/// All interfaces read so far.
+	pub fn interfaces(&self) -> &[Interface] {
+		&self.interfaces
+	}
+
+	/// Number of bytes up to the end of the last block read.
+	pub fn position(&self) -> u64 {
+		self.position
+	}
+
+	/// Section headers and interface descriptions read so far. Followed by the blocks after
+	/// `position`, they form a valid file with the same interfaces.
+	pub fn headers(&self) -> &[u8] {
+		&self.headers
+	}
+
+	/// Returns the next packet or `None` at the end of the file. Blocks other than section
+	/// headers, interface descriptions and packets are skipped.
+	pub fn next_record(&mut self) -> io::Result<Option<Record>> {
+		loop {
+
+			let mut header = [0u8; 8];
+			match self.reader.read_exact(&mut header) {
+				Ok(()) => {},
+				Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+				Err(e) => return Err(e),
+			}
+
+			let block_type = self.u32_at(&header, 0);
+
+			if block_type == BLOCK_SECTION_HEADER {
+				// The block type is a palindrome, so the byte order is read before the length.
+				let mut magic = [0u8; 4];
+				self.reader.read_exact(&mut magic)?;
+				self.big_endian = match (u32::from_le_bytes(magic), u32::from_be_bytes(magic)) {
+					(BYTE_ORDER_MAGIC, _) => false,
+					(_, BYTE_ORDER_MAGIC) => true,
+					_ => return Err(invalid("invalid byte-order magic")),
+				};
+				let body = self.read_body(&header, 4)?;
+				self.read_section(&body);
+				self.add_header(&header, &magic, &body);
+				continue;
+			}
+
+			if self.sections.is_empty() {
+				return Err(invalid("not a pcapng file"));
+			}
+
+			let body = self.read_body(&header, 0)?;
+
+			match block_type {
+				BLOCK_INTERFACE_DESCRIPTION => {
+					self.read_interface(&body)?;
+					self.add_header(&header, &[], &body);
+				},
+				BLOCK_ENHANCED_PACKET => return self.read_enhanced_packet(&body).map(Some),
+				BLOCK_PACKET => return self.read_packet(&body).map(Some),
+				BLOCK_SIMPLE_PACKET => warn!("Skipping simple packet block without timestamp."),
+				_ => trace!("Skipping pcapng block of type {:#x}.", block_type),
+			}
+
+		}
+	}
+
+	/// Reads the remaining block body including the trailing length. `consumed` bytes of
+	/// the body have been read already.
+	fn read_body(&mut self, header: &[u8], consumed: usize) -> io::Result<Vec<u8>> {
+		let block_len = self.u32_at(header, 4) as usize;
+		if block_len < 12 + consumed || block_len & 3 != 0 || block_len > MAX_BLOCK_LEN {
+			return Err(invalid("invalid block length"));
+		}
+		let mut body = vec![0u8; block_len - 8 - consumed];
+		self.reader.read_exact(&mut body)?;
+		self.position += block_len as u64;
+		// Strip the trailing copy of the block length.
+		let len = body.len();
+		body.truncate(len - 4);
+		Ok(body)
+	}
+
+	/// Keeps a copy of a block that is needed to read on at a later position.
+	fn add_header(&mut self, header: &[u8], magic: &[u8], body: &[u8]) {
+		self.headers.extend_from_slice(header);
+		self.headers.extend_from_slice(magic);
+		self.headers.extend_from_slice(body);
+		self.headers.extend_from_slice(&header[4..8]);
+	}
+
+	fn read_section(&mut self, body: &[u8]) {
+		let mut section = Section::default();
+		// Major and minor version and the section length precede the options.
+		for (code, value) in self.options(body, 12) {
+			match code {
+				OPT_COMMENT => section.comment = Some(string(value)),
+				OPT_SHB_HARDWARE => section.hardware = Some(string(value)),
+				OPT_SHB_OS => section.os = Some(string(value)),
+				OPT_SHB_USERAPPL => section.application = Some(string(value)),
+				_ => {},
+			}
+		}
+		self.sections.push(section);
+		self.section_start = self.interfaces.len();
+	}
+
+	fn read_interface(&mut self, body: &[u8]) -> io::Result<()> {
+		if body.len() < 8 {
+			return Err(invalid("interface description block too short"));
+		}
+		let mut interface = Interface {
+			link_type: self.u16_at(body, 0),
+			name: None,
+			description: None,
+			resolution: Resolution::default(),
+			offset: 0,
+		};
+		for (code, value) in self.options(body, 8) {
+			match code {
+				OPT_IF_NAME => interface.name = Some(string(value)),
+				OPT_IF_DESCRIPTION => interface.description = Some(string(value)),
+				OPT_IF_TSRESOL if value.len() == 1 => interface.resolution = Resolution::from_tsresol(value[0]),
+				OPT_IF_TSOFFSET if value.len() == 8 => interface.offset = self.u64_at(value, 0) as i64,
+				_ => {},
+			}
+		}
+		self.interfaces.push(interface);
+		Ok(())
+	}
+
+	fn read_enhanced_packet(&self, body: &[u8]) -> io::Result<Record> {
+		if body.len() < 20 {
+			return Err(invalid("enhanced packet block too short"));
+		}
+		let interface = self.u32_at(body, 0) as usize;
+		let ts = ((self.u32_at(body, 4) as u64) << 32) | self.u32_at(body, 8) as u64;
+		let captured_len = self.u32_at(body, 12) as usize;
+		self.record(body, interface, ts, 20, captured_len)
+	}
+
+	/// Reads the obsolete packet block written by old versions of Wireshark.
+	fn read_packet(&self, body: &[u8]) -> io::Result<Record> {
+		if body.len() < 20 {
+			return Err(invalid("packet block too short"));
+		}
+		let interface = self.u16_at(body, 0) as usize;
+		let ts = ((self.u32_at(body, 4) as u64) << 32) | self.u32_at(body, 8) as u64;
+		let captured_len = self.u32_at(body, 12) as usize;
+		self.record(body, interface, ts, 20, captured_len)
+	}
+
+	fn record(&self, body: &[u8], interface: usize, ts: u64, data_start: usize, captured_len: usize) -> io::Result<Record> {
+
+		let interface = self.section_start + interface;
+		let resolution = match self.interfaces.get(interface) {
+			Some(i) => i.resolution,
+			None => return Err(invalid("packet refers to an unknown interface")),
+		};
+
+		let data_end = data_start + captured_len;
+		if data_end > body.len() {
+			return Err(invalid("captured length exceeds block"));
+		}
+
+		let comment = self.options(body, data_start + ((captured_len + 3) & !3))
+			.into_iter()
+			.find(|&(code, _)| code == OPT_COMMENT)
+			.map(|(_, value)| string(value));
+
+		let mut ts = to_timespec(ts, resolution);
+		ts.sec += self.interfaces[interface].offset;
+
+		Ok(Record {
+			interface,
+			ts,
+			data: body[data_start..data_end].to_vec(),
+			comment,
+		})
+	}
+
+	/// Splits the options starting at `start` into code and value.
+	fn options<'b>(&self, body: &'b [u8], start: usize) -> Vec<(u16, &'b [u8])> {
+		let mut options = Vec::new();
+		let mut pos = start;
+		while pos + 4 <= body.len() {
+			let code = self.u16_at(body, pos);
+			let len = self.u16_at(body, pos + 2) as usize;
+			if code == OPT_END || pos + 4 + len > body.len() {
+				break;
+			}
+			options.push((code, &body[pos+4..pos+4+len]));
+			pos += 4 + ((len + 3) & !3);
+		}
+		options
+	}
+
+	fn u16_at(&self, data: &[u8], offset: usize) -> u16 {
+		let bytes = [data[offset], data[offset+1]];
+		if self.big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) }
+	}
+
+	fn u32_at(&self, data: &[u8], offset: usize) -> u32 {
+		let bytes = [data[offset], data[offset+1], data[offset+2], data[offset+3]];
+		if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) }
+	}
+
+	fn u64_at(&self, data: &[u8], offset: usize) -> u64 {
+		let mut bytes = [0u8; 8];
+		bytes.copy_from_slice(&data[offset..offset+8]);
+		if self.big_endian { u64::from_be_bytes(bytes) } else { u64::from_le_bytes(bytes) }
+	}
+
+}
+
+/// Converts a timestamp in units of `resolution` into exact (truncated) nanoseconds.
+fn to_timespec(ts: u64, resolution: Resolution) -> Timespec {
+	let units_per_second: u128 = match resolution {
+		Resolution::Decimal(n) => 10u128.checked_pow(n as u32).unwrap_or(u128::MAX),
+		Resolution::Binary(n) => 1u128 << n,
+	};
+	let ts = ts as u128;
+	Timespec {
+		sec: (ts / units_per_second) as i64,
+		nsec: ((ts % units_per_second) * 1_000_000_000 / units_per_second) as i32,
+	}
+}
+
+fn string(value: &[u8]) -> String {
+	String::from_utf8_lossy(value).trim_end_matches('\0').to_owned()
+}
+
+fn invalid(message: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use std::io::Cursor;
+
+	fn u16_bytes(value: u16, big_endian: bool) -> [u8; 2] {
+		if big_endian { value.to_be_bytes() } else { value.to_le_bytes() }
+	}
+
+	fn u32_bytes(value: u32, big_endian: bool) -> [u8; 4] {
+		if big_endian { value.to_be_bytes() } else { value.to_le_bytes() }
+	}
+
+	fn block(block_type: u32, body: &[u8], big_endian: bool) -> Vec<u8> {
+		let len = 12 + body.len() as u32;
+		let mut block = Vec::new();
+		block.extend_from_slice(&u32_bytes(block_type, big_endian));
+		block.extend_from_slice(&u32_bytes(len, big_endian));
+		block.extend_from_slice(body);
+		block.extend_from_slice(&u32_bytes(len, big_endian));
+		block
+	}
+
+	/// Appends an option padded to 32 bits.
+	fn option(body: &mut Vec<u8>, code: u16, value: &[u8], big_endian: bool) {
+		body.extend_from_slice(&u16_bytes(code, big_endian));
+		body.extend_from_slice(&u16_bytes(value.len() as u16, big_endian));
+		body.extend_from_slice(value);
+		while body.len() & 3 != 0 {
+			body.push(0);
+		}
+	}
+
+	fn section(big_endian: bool, options: &[(u16, &[u8])]) -> Vec<u8> {
+		let mut body = Vec::new();
+		body.extend_from_slice(&u32_bytes(BYTE_ORDER_MAGIC, big_endian));
+		body.extend_from_slice(&u16_bytes(1, big_endian));
+		body.extend_from_slice(&u16_bytes(0, big_endian));
+		body.extend_from_slice(&[0xFF; 8]);
+		for &(code, value) in options {
+			option(&mut body, code, value, big_endian);
+		}
+		block(BLOCK_SECTION_HEADER, &body, big_endian)
+	}
+
+	fn interface(big_endian: bool, link_type: u16, options: &[(u16, &[u8])]) -> Vec<u8> {
+		let mut body = Vec::new();
+		body.extend_from_slice(&u16_bytes(link_type, big_endian));
+		body.extend_from_slice(&[0, 0]);
+		body.extend_from_slice(&u32_bytes(0, big_endian));
+		for &(code, value) in options {
+			option(&mut body, code, value, big_endian);
+		}
+		block(BLOCK_INTERFACE_DESCRIPTION, &body, big_endian)
+	}
+
+	fn enhanced_packet(big_endian: bool, interface: u32, ts: u64, data: &[u8], comment: Option<&str>) -> Vec<u8> {
+		let mut body = Vec::new();
+		body.extend_from_slice(&u32_bytes(interface, big_endian));
+		body.extend_from_slice(&u32_bytes((ts >> 32) as u32, big_endian));
+		body.extend_from_slice(&u32_bytes(ts as u32, big_endian));
+		body.extend_from_slice(&u32_bytes(data.len() as u32, big_endian));
+		body.extend_from_slice(&u32_bytes(data.len() as u32, big_endian));
+		body.extend_from_slice(data);
+		while body.len() & 3 != 0 {
+			body.push(0);
+		}
+		if let Some(comment) = comment {
+			option(&mut body, OPT_COMMENT, comment.as_bytes(), big_endian);
+		}
+		block(BLOCK_ENHANCED_PACKET, &body, big_endian)
+	}
+
+	#[test]
+	fn little_endian_with_options() {
+		let mut file = section(false, &[(OPT_SHB_HARDWARE, b"x86"), (OPT_SHB_USERAPPL, b"test")]);
+		file.extend(interface(false, 1, &[(OPT_IF_NAME, b"eth0"), (OPT_IF_TSRESOL, &[3])]));
+		file.extend(enhanced_packet(false, 0, 1_234_567, &[1, 2, 3, 4, 5], Some("first")));
+		file.extend(block(0x0000_0005, &[0; 8], false));
+		file.extend(enhanced_packet(false, 0, 1_235_000, &[6, 7, 8], None));
+
+		let mut reader = PcapngReader::new(Cursor::new(file.clone()));
+		let record = reader.next_record().unwrap().unwrap();
+		assert_eq!(record.interface, 0);
+		assert_eq!(record.ts, Timespec::new(1234, 567_000_000));
+		assert_eq!(record.data, vec![1, 2, 3, 4, 5]);
+		assert_eq!(record.comment, Some("first".to_owned()));
+
+		assert_eq!(reader.sections().len(), 1);
+		assert_eq!(reader.sections()[0].hardware, Some("x86".to_owned()));
+		assert_eq!(reader.sections()[0].application, Some("test".to_owned()));
+		assert_eq!(reader.sections()[0].os, None);
+		assert_eq!(reader.interfaces().len(), 1);
+		assert_eq!(reader.interfaces()[0].link_type, 1);
+		assert_eq!(reader.interfaces()[0].name, Some("eth0".to_owned()));
+		assert_eq!(reader.interfaces()[0].resolution, Resolution::Decimal(3));
+
+		// The unknown block is skipped.
+		let record = reader.next_record().unwrap().unwrap();
+		assert_eq!(record.ts, Timespec::new(1235, 0));
+		assert_eq!(record.data, vec![6, 7, 8]);
+		assert_eq!(record.comment, None);
+		assert!(reader.next_record().unwrap().is_none());
+		assert_eq!(reader.position(), file.len() as u64);
+	}
+
+	#[test]
+	fn big_endian_binary_resolution_and_offset() {
+		let mut file = section(true, &[]);
+		file.extend(interface(true, 113, &[(OPT_IF_TSRESOL, &[0x84]), (OPT_IF_TSOFFSET, &100u64.to_be_bytes())]));
+		// 33.5 s in units of 1/16 s
+		file.extend(enhanced_packet(true, 0, 33 * 16 + 8, &[0xAB], None));
+
+		let mut reader = PcapngReader::new(Cursor::new(file));
+		let record = reader.next_record().unwrap().unwrap();
+		assert_eq!(reader.interfaces()[0].link_type, 113);
+		assert_eq!(reader.interfaces()[0].resolution, Resolution::Binary(4));
+		assert_eq!(reader.interfaces()[0].offset, 100);
+		assert_eq!(record.ts, Timespec::new(133, 500_000_000));
+		assert_eq!(record.data, vec![0xAB]);
+	}
+
+	#[test]
+	fn interfaces_are_counted_across_sections() {
+		let mut file = section(false, &[]);
+		file.extend(interface(false, 1, &[]));
+		file.extend(section(true, &[]));
+		file.extend(interface(true, 276, &[]));
+		file.extend(enhanced_packet(true, 0, 1_000_000, &[0], None));
+
+		let mut reader = PcapngReader::new(Cursor::new(file));
+		let record = reader.next_record().unwrap().unwrap();
+		assert_eq!(record.interface, 1);
+		// Microseconds are the default resolution.
+		assert_eq!(record.ts, Timespec::new(1, 0));
+		assert_eq!(reader.sections().len(), 2);
+		assert_eq!(reader.interfaces()[1].link_type, 276);
+	}
+
+	#[test]
+	fn headers_continue_at_position() {
+		let mut file = section(false, &[]);
+		file.extend(interface(false, 1, &[(OPT_IF_TSRESOL, &[9])]));
+		file.extend(enhanced_packet(false, 0, 1, &[1], None));
+		file.extend(enhanced_packet(false, 0, 2, &[2], None));
+
+		let mut reader = PcapngReader::new(Cursor::new(file.clone()));
+		reader.next_record().unwrap().unwrap();
+		let mut rest = reader.headers().to_vec();
+		rest.extend_from_slice(&file[reader.position() as usize..]);
+
+		let mut reader = PcapngReader::new(Cursor::new(rest));
+		let record = reader.next_record().unwrap().unwrap();
+		assert_eq!(record.ts, Timespec::new(0, 2));
+		assert_eq!(record.data, vec![2]);
+		assert!(reader.next_record().unwrap().is_none());
+	}
+
+	#[test]
+	fn invalid_files() {
+		let packet = enhanced_packet(false, 0, 0, &[], None);
+		assert!(PcapngReader::new(Cursor::new(packet)).next_record().is_err());
+
+		let mut file = section(false, &[]);
+		file.extend(enhanced_packet(false, 0, 0, &[], None));
+		assert!(PcapngReader::new(Cursor::new(file)).next_record().is_err());
+
+		let mut file = section(false, &[]);
+		file[4] = 13;
+		assert!(PcapngReader::new(Cursor::new(file)).next_record().is_err());
+
+		assert!(PcapngReader::new(Cursor::new(Vec::new())).next_record().unwrap().is_none());
+	}
+
+}
@@ -0,0 +1,312 @@
+// Powerlink Analyzer - Analyze Ethernet POWERLINK Network Traffic
+// Copyright (C) 2016, Thomas Keh
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, version 3 of the License.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The PDO module extracts process data from PReq/PRes payloads using a user-supplied mapping.
+//!
+//! A mapping file contains one mapped object per line:
+//!
+//! ```text
+//! # node  frame  object       bit_offset  bit_length  type
+//! 1       pres   0x6000/0x01  0           16          u16
+//! 1       preq   0x6200/0x01  16          1           bool
+//! ```
+//!
+//! `pres` objects are read from the PRes sent by the node, `preq` objects from the PReq
+//! sent to the node. Supported types are bool, u8-u64, i8-i64, f32 and f64.
+
+use std::fs::File;
+use std::io::{BufRead,BufReader};
+use std::path::Path;
+use types::PacketType;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PdoType {
+	Bool,
+	Unsigned,
+	Signed,
+	Float,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum PdoValue {
+	Int(i64),
+	Float(f64),
+}
+
+#[derive(Debug, Clone)]
+pub struct PdoObject {
+	pub node_id: u8,
+	pub direction: PacketType,
+	pub index: u16,
+	pub subindex: u8,
+	pub bit_offset: usize,
+	pub bit_length: usize,
+	pub data_type: PdoType,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PdoMapping {
+	objects: Vec<PdoObject>,
+}
+
+impl PdoMapping {
+
+	pub fn from_file(path: &Path) -> Result<Self, String> {
+
+		let file = File::open(path).map_err(|e| format!("Cannot open PDO mapping {}: {}", path.display(), e))?;
+		let mut mapping = PdoMapping::default();
+
+		for (i, line) in BufReader::new(file).lines().enumerate() {
+			let line = line.map_err(|e| e.to_string())?;
+			let line = line.split('#').next().unwrap().trim();
+			if line.is_empty() {
+				continue;
+			}
+			let object = PdoMapping::parse_line(line).map_err(|e| format!("{}:{}: {}", path.display(), i+1, e))?;
+			mapping.objects.push(object);
+		}
+
+		Ok(mapping)
+	}
+
+	fn parse_line(line: &str) -> Result<PdoObject, String> {
+
+		let fields: Vec<&str> = line.split_whitespace().collect();
+		if fields.len() != 6 {
+			return Err(format!("expected 6 fields, got {}", fields.len()));
+		}
+
+		let node_id = fields[0].parse::<u8>().map_err(|e| format!("invalid node ID: {}", e))?;
+
+		let direction = match fields[1] {
+			"preq" => PacketType::PReq,
+			"pres" => PacketType::PRes,
+			other => return Err(format!("invalid frame type {}, expected preq or pres", other)),
+		};
+
+		let mut object = fields[2].splitn(2, '/');
+		let index = parse_number(object.next().unwrap()).filter(|&i| i <= 0xFFFF).ok_or("invalid object index")?;
+		let subindex = parse_number(object.next().unwrap_or("0")).filter(|&i| i <= 0xFF).ok_or("invalid object subindex")?;
+
+		let bit_offset = fields[3].parse::<usize>().map_err(|e| format!("invalid bit offset: {}", e))?;
+		let bit_length = fields[4].parse::<usize>().map_err(|e| format!("invalid bit length: {}", e))?;
+
+		let (data_type, max_length) = match fields[5] {
+			"bool" => (PdoType::Bool, 1),
+			"u8" | "u16" | "u32" | "u64" => (PdoType::Unsigned, fields[5][1..].parse().unwrap()),
+			"i8" | "i16" | "i32" | "i64" => (PdoType::Signed, fields[5][1..].parse().unwrap()),
+			"f32" | "f64" => (PdoType::Float, fields[5][1..].parse().unwrap()),
+			other => return Err(format!("unknown type {}", other)),
+		};
+
+		if bit_length == 0 || bit_length > max_length || (data_type == PdoType::Float && bit_length != max_length) {
+			return Err(format!("bit length {} does not fit type {}", bit_length, fields[5]));
+		}
+
+		Ok(PdoObject {
+			node_id,
+			direction,
+			index: index as u16,
+			subindex: subindex as u8,
+			bit_offset,
+			bit_length,
+			data_type,
+		})
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.objects.is_empty()
+	}
+
+	/// Extracts all objects mapped for `node_id` in frames of type `direction`. Objects
+	/// beyond the end of the payload are skipped.
+	pub fn extract(&self, node_id: u8, direction: PacketType, payload: &[u8]) -> Vec<(&PdoObject, PdoValue)> {
+		self.objects.iter()
+			.filter(|o| o.node_id == node_id && o.direction == direction)
+			.filter_map(|o| o.extract(payload).map(|v| (o, v)))
+			.collect()
+	}
+
+}
+
+impl PdoObject {
+
+	fn extract(&self, payload: &[u8]) -> Option<PdoValue> {
+
+		if self.bit_offset + self.bit_length > payload.len() * 8 {
+			return None;
+		}
+
+		// PDO data is little endian, so bit n of the payload is bit n%8 of byte n/8.
+		let mut raw = 0u64;
+		for bit in 0..self.bit_length {
+			let pos = self.bit_offset + bit;
+			if payload[pos / 8] & (1 << (pos % 8)) != 0 {
+				raw |= 1 << bit;
+			}
+		}
+
+		Some(match self.data_type {
+			PdoType::Bool | PdoType::Unsigned => PdoValue::Int(raw as i64),
+			PdoType::Signed => {
+				let shift = 64 - self.bit_length;
+				PdoValue::Int(((raw << shift) as i64) >> shift)
+			},
+			PdoType::Float if self.bit_length == 32 => PdoValue::Float(f32::from_bits(raw as u32) as f64),
+			PdoType::Float => PdoValue::Float(f64::from_bits(raw)),
+		})
+	}
+
+}
+
+fn parse_number(s: &str) -> Option<u32> {
+	if s.starts_with("0x") || s.starts_with("0X") {
+		u32::from_str_radix(&s[2..], 16).ok()
+	} else {
+		s.parse().ok()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+
+	fn object(line: &str) -> PdoObject {
+		PdoMapping::parse_line(line).unwrap()
+	}
+
+	fn int(line: &str, payload: &[u8]) -> i64 {
+		match object(line).extract(payload) {
+			Some(PdoValue::Int(value)) => value,
+			other => panic!("{} extracted {:?}", line, other),
+		}
+	}
+
+	fn float(line: &str, payload: &[u8]) -> f64 {
+		match object(line).extract(payload) {
+			Some(PdoValue::Float(value)) => value,
+			other => panic!("{} extracted {:?}", line, other),
+		}
+	}
+
+	#[test]
+	fn parse_line() {
+		let object = object("12 preq 0x6200/0x01 16 1 bool");
+		assert_eq!(object.node_id, 12);
+		assert_eq!(object.direction, PacketType::PReq);
+		assert_eq!((object.index, object.subindex), (0x6200, 1));
+		assert_eq!((object.bit_offset, object.bit_length), (16, 1));
+		assert_eq!(object.data_type, PdoType::Bool);
+
+		// The subindex defaults to 0 and numbers may be decimal.
+		let object = self::object("1 pres 24576 0 32 f32");
+		assert_eq!(object.direction, PacketType::PRes);
+		assert_eq!((object.index, object.subindex), (0x6000, 0));
+		assert_eq!(object.data_type, PdoType::Float);
+	}
+
+	#[test]
+	fn parse_line_errors() {
+		for line in &[
+			"1 pres 0x6000/0x01 0 16",
+			"256 pres 0x6000/0x01 0 16 u16",
+			"1 soa 0x6000/0x01 0 16 u16",
+			"1 pres 0x10000/0x01 0 16 u16",
+			"1 pres 0xZZ/0x01 0 16 u16",
+			"1 pres 0x6000/ 0 16 u16",
+			"1 pres 0x6000/0x100 0 16 u16",
+			"1 pres 0x6000/0x01 x 16 u16",
+			"1 pres 0x6000/0x01 0 16 u128",
+			"1 pres 0x6000/0x01 0 16 string",
+			"1 pres 0x6000/0x01 0 0 u16",
+			"1 pres 0x6000/0x01 0 17 u16",
+			"1 pres 0x6000/0x01 0 2 bool",
+			"1 pres 0x6000/0x01 0 16 f32",
+		] {
+			assert!(PdoMapping::parse_line(line).is_err(), "{} was accepted", line);
+		}
+		assert_eq!(PdoMapping::parse_line("1 pres 0x6000/0x01 0 16 s16").unwrap_err(), "unknown type s16");
+		assert_eq!(PdoMapping::parse_line("1 pres 0x6000/ 0 16 u16").unwrap_err(), "invalid object subindex");
+		assert_eq!(PdoMapping::parse_line("1 pres 0x/0x01 0 16 u16").unwrap_err(), "invalid object index");
+	}
+
+	#[test]
+	fn extract_unsigned() {
+		let payload = [0x34, 0x12, 0xF0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x0F];
+		assert_eq!(int("1 pres 0x6000/1 0 8 u8", &payload), 0x34);
+		assert_eq!(int("1 pres 0x6000/1 0 16 u16", &payload), 0x1234);
+		assert_eq!(int("1 pres 0x6000/1 0 32 u32", &payload), 0xFFF0_1234);
+		// Bits 4-11 cross the boundary of the first two bytes.
+		assert_eq!(int("1 pres 0x6000/1 4 8 u8", &payload), 0x23);
+		assert_eq!(int("1 pres 0x6000/1 12 12 u16", &payload), 0xF01);
+		// All 64 bits, the value is kept as bit pattern.
+		assert_eq!(int("1 pres 0x6000/1 20 64 u64", &payload) as u64, 0xFFFF_FFFF_FFFF_FFFF);
+		assert_eq!(int("1 pres 0x6000/1 16 64 u64", &payload) as u64, 0xFFFF_FFFF_FFFF_FFF0);
+	}
+
+	#[test]
+	fn extract_bool() {
+		let payload = [0b0000_0100, 0b1000_0000];
+		assert_eq!(int("1 pres 0x6000/1 2 1 bool", &payload), 1);
+		assert_eq!(int("1 pres 0x6000/1 3 1 bool", &payload), 0);
+		assert_eq!(int("1 pres 0x6000/1 15 1 bool", &payload), 1);
+	}
+
+	#[test]
+	fn extract_signed() {
+		let payload = [0xFE, 0xFF, 0x7F, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80];
+		assert_eq!(int("1 pres 0x6000/1 0 8 i8", &payload), -2);
+		assert_eq!(int("1 pres 0x6000/1 0 16 i16", &payload), -2);
+		assert_eq!(int("1 pres 0x6000/1 16 8 i8", &payload), 127);
+		assert_eq!(int("1 pres 0x6000/1 24 8 i8", &payload), -128);
+		// 12 bits from bit 4 are 0xFFF, 5 bits from bit 19 are 0b01111 and 10 bits from
+		// bit 22 are 0b10_0000_0001.
+		assert_eq!(int("1 pres 0x6000/1 4 12 i16", &payload), -1);
+		assert_eq!(int("1 pres 0x6000/1 19 5 i8", &payload), 15);
+		assert_eq!(int("1 pres 0x6000/1 22 10 i16", &payload), -511);
+		assert_eq!(int("1 pres 0x6000/1 24 64 i64", &payload), i64::MIN + 0x80);
+		assert_eq!(int("1 pres 0x6000/1 16 64 i64", &payload), 0x807F);
+	}
+
+	#[test]
+	fn extract_float() {
+		let mut payload = 1.5f32.to_bits().to_le_bytes().to_vec();
+		payload.extend_from_slice(&(-0.25f64).to_bits().to_le_bytes());
+		assert_eq!(float("1 pres 0x6000/1 0 32 f32", &payload), 1.5);
+		assert_eq!(float("1 pres 0x6000/1 32 64 f64", &payload), -0.25);
+	}
+
+	#[test]
+	fn extract_beyond_payload() {
+		let payload = [0xFF, 0xFF];
+		assert!(object("1 pres 0x6000/1 0 16 u16").extract(&payload).is_some());
+		assert!(object("1 pres 0x6000/1 1 16 u16").extract(&payload).is_none());
+		assert!(object("1 pres 0x6000/1 16 1 bool").extract(&payload).is_none());
+	}
+
+	#[test]
+	fn extract_by_node_and_direction() {
+		let mut mapping = PdoMapping::default();
+		mapping.objects.push(object("1 pres 0x6000/1 0 8 u8"));
+		mapping.objects.push(object("1 preq 0x6200/1 0 8 u8"));
+		mapping.objects.push(object("2 pres 0x6000/1 8 8 u8"));
+		let values = mapping.extract(1, PacketType::PRes, &[5, 6]);
+		assert_eq!(values.len(), 1);
+		assert_eq!(values[0].0.index, 0x6000);
+		assert!(mapping.extract(3, PacketType::PRes, &[5, 6]).is_empty());
+	}
+
+}
@@ -16,22 +16,76 @@
 //! The Plkan module does the actual analysis work.
 
 use time::{Timespec,Duration};
-use pcap::*;
 use types::*;
-use num::FromPrimitive;
 use database::*;
+use frame::*;
+use pdo::PdoMapping;
+use sdo::*;
+use nmt::NmtCommand;
+use ident::IdentResponse;
+use status::StatusResponse;
+use window::Window;
+use cycle::Cycle;
+
+/// The protocol state needed to continue an analysis in the middle of a capture. Open SDO
+/// transfers, pending NMT commands and the cycle in progress are not part of it.
+#[derive(Debug, Clone, Default)]
+pub struct PlkanState {
+	pub packet_id: usize,
+	pub cycle: u64,
+	pub first_ts: Option<Timespec>,
+	pub soc_ts: Option<Timespec>,
+	pub soc_packet_id: Option<usize>,
+	pub mn_state: Option<NmtState>,
+	/// Known CN states by node ID.
+	pub cn_states: Vec<(u8,NmtState)>,
+	pub request_type: Option<PacketType>,
+	pub request_service: Option<ServiceId>,
+	pub requested_node: Option<u8>,
+	pub request_ts: Option<Timespec>,
+	pub request_packet_id: Option<usize>,
+	pub request_cycle: u64,
+	/// Time and cycle the window is anchored at.
+	pub anchor: Option<(Timespec,u64)>,
+}
+
+/// An NMT state command waiting for the addressed node to change its state.
+#[derive(Clone, Copy)]
+struct PendingCommand {
+	/// Database row of the command, `None` if it was not stored.
+	row_id: Option<i64>,
+	expected: Option<NmtState>,
+	timestamp: Duration,
+}
 
 pub struct Plkan<'a> {
 	db: &'a mut Database,
 	soc_ts: Option<Timespec>,
+	soc_packet_id: Option<usize>,
 	request_ts: Option<Timespec>,
+	request_packet_id: Option<usize>,
+	request_cycle: u64,
 	request_type: Option<PacketType>,
 	request_service: Option<ServiceId>,
 	requested_node: Option<u8>,
+	/// Node, time and packet of the last CN PRes that the MN has not reacted to yet.
+	last_pres: Option<(u8,Timespec,usize)>,
 	mn_state: Option<NmtState>,
 	cn_state: [Option<NmtState>; 256],
 	first_ts: Option<Timespec>,
 	packet_id: usize,
+	exception_new: [Option<bool>; 256],
+	exception_ts: [Option<Timespec>; 256],
+	cycle: u64,
+	pdo_mapping: PdoMapping,
+	sdo_tracker: SdoTracker,
+	/// Last NMT state command per node.
+	nmt_pending: [Option<PendingCommand>; 256],
+	static_errors: [Option<[u8; 8]>; 256],
+	file_id: Option<usize>,
+	window: Window,
+	replaying: bool,
+	current_cycle: Option<Cycle>,
 }
 
 impl<'a> Plkan<'a> {
@@ -40,66 +94,242 @@ impl<'a> Plkan<'a> {
 		Plkan {
 			db: database,
 			soc_ts: None,
+			soc_packet_id: None,
 			request_ts: None,
+			request_packet_id: None,
+			request_cycle: 0,
 			request_type: None,
 			request_service: None,
 			requested_node: None,
+			last_pres: None,
 			mn_state: None,
 			cn_state: [None::<NmtState>; 256],
 			first_ts: None,
 			packet_id: 0,
+			exception_new: [None; 256],
+			exception_ts: [None; 256],
+			cycle: 0,
+			pdo_mapping: PdoMapping::default(),
+			sdo_tracker: SdoTracker::default(),
+			nmt_pending: [None; 256],
+			static_errors: [None; 256],
+			file_id: None,
+			window: Window::default(),
+			replaying: false,
+			current_cycle: None,
 		}
 	}
 
-	pub fn process_packet(&mut self, packet: &Packet) {
-		
+	pub fn set_pdo_mapping(&mut self, mapping: PdoMapping) {
+		self.pdo_mapping = mapping;
+	}
+
+	/// Restricts the stored results to the packets within `window`.
+	pub fn set_window(&mut self, window: Window) {
+		self.window = window;
+	}
+
+	/// Replayed packets only update the network state, their results are stored already.
+	pub fn set_replaying(&mut self, replaying: bool) {
+		self.replaying = replaying;
+	}
+
+	/// Returns the state after the last processed packet.
+	pub fn state(&self) -> PlkanState {
+		PlkanState {
+			packet_id: self.packet_id,
+			cycle: self.cycle,
+			first_ts: self.first_ts,
+			soc_ts: self.soc_ts,
+			soc_packet_id: self.soc_packet_id,
+			mn_state: self.mn_state,
+			cn_states: (0..256).filter_map(|node| self.cn_state[node].map(|state| (node as u8, state))).collect(),
+			request_type: self.request_type,
+			request_service: self.request_service,
+			requested_node: self.requested_node,
+			request_ts: self.request_ts,
+			request_packet_id: self.request_packet_id,
+			request_cycle: self.request_cycle,
+			anchor: self.window.anchor_point(),
+		}
+	}
+
+	/// Continues after the packets described by `state`, which are not processed again.
+	pub fn restore(&mut self, state: &PlkanState) {
+		self.packet_id = state.packet_id;
+		self.cycle = state.cycle;
+		self.first_ts = state.first_ts;
+		self.soc_ts = state.soc_ts;
+		self.soc_packet_id = state.soc_packet_id;
+		self.mn_state = state.mn_state;
+		for &(node, cn_state) in &state.cn_states {
+			self.cn_state[node as usize] = Some(cn_state);
+		}
+		self.request_type = state.request_type;
+		self.request_service = state.request_service;
+		self.requested_node = state.requested_node;
+		self.request_ts = state.request_ts;
+		self.request_packet_id = state.request_packet_id;
+		self.request_cycle = state.request_cycle;
+		if let Some((ts, cycle)) = state.anchor {
+			self.window.anchor(ts, cycle);
+		}
+	}
+
+	/// Gives access to the results while packets are still being processed.
+	pub fn database(&self) -> &Database {
+		self.db
+	}
+
+	/// Sets the capture file of the following packets.
+	pub fn set_file(&mut self, file_id: usize) {
+		if self.file_id != Some(file_id) {
+			self.file_id = Some(file_id);
+			self.db.insert_file_run(file_id, self.packet_id + 1);
+		}
+	}
+
+	/// Attaches a capture comment to the last processed packet.
+	pub fn add_comment(&self, interface_id: usize, comment: &str) {
+		self.db.insert_comment(self.packet_id, self.file_id.unwrap_or(0), interface_id, comment);
+	}
+
+	/// Stores the results that are only complete at the end of the capture, like SDO
+	/// transfers that were never finished.
+	pub fn finish(&mut self) {
+		if let Some(first_ts) = self.first_ts {
+			for transfer in self.sdo_tracker.finish_all() {
+				self.db.insert_sdo_transfer(&transfer, transfer.start-first_ts);
+			}
+		}
+	}
+
+	/// Processes a packet of the given link-layer type captured at `ts`.
+	pub fn process_packet(&mut self, link_type: LinkType, ts: Timespec, data: &[u8]) {
+
 		if let Some(first_ts) = self.first_ts {
-			self.db.notify_packet(self.get_timespec(packet) - first_ts);
+			self.db.notify_packet(ts - first_ts);
 		} else {
-			self.first_ts = Some(self.get_timespec(packet));
+			self.first_ts = Some(ts);
 			self.db.notify_packet(Duration::zero());
+			if !self.window.from_operational {
+				self.window.anchor(ts, 0);
+			}
 		}
 
 		self.packet_id += 1;
 
-		if !Plkan::is_powerlink(packet) {
-			
-			if self.request_type==Some(PacketType::SoA) && self.request_service == Some(ServiceId::Unspec) {
-				trace!("Got VETH packet.");
-			} else {
-				trace!("Got non-Powerlink packet, but VETH is not expected: {:?}", packet);
-				// CN state has no meaning here.
-				match self.requested_node {
-					Some (node) => self.db.insert_error("unexpected_veth",node,self.mn_state,None),
-					None        => self.db.insert_error("interference",0,self.mn_state,None),
+		let header = LinkHeader::parse(link_type, data);
+
+		let payload = match header {
+			Some(ref header) if header.is_powerlink() => Some(&data[header.len..]),
+			_ => None,
+		};
+
+		let frame = payload.map(Frame::parse);
+
+		// A SoC belongs to the cycle it starts.
+		let cycle = match frame {
+			Some(Some(Frame::SoC(_))) => self.cycle + 1,
+			_ => self.cycle,
+		};
+		self.db.set_recording(!self.replaying && (!self.window.is_set() || self.window.contains(ts, cycle)));
+
+		if let Some(LinkHeader { vlan_id: Some(vlan_id), outer_vlan_id, .. }) = header {
+			self.db.insert_vlan(vlan_id, outer_vlan_id, self.packet_id);
+		}
+
+		match frame {
+			Some(Some(ref frame)) => self.track_cycle(Some(frame), ts),
+			None => self.track_cycle(None, ts),
+			Some(None) => {},
+		}
+
+		match frame {
+
+			None => {
+				self.last_pres = None;
+				if self.request_type==Some(PacketType::SoA) && self.request_service == Some(ServiceId::Unspec) {
+					trace!("Got VETH packet.");
+				} else {
+					trace!("Got non-Powerlink packet, but VETH is not expected: {:?}", data);
+					// CN state has no meaning here.
+					match self.requested_node {
+						Some (node) => self.db.insert_error("unexpected_veth",node,&self.origin(ts),self.mn_state,None),
+						None        => self.db.insert_error("interference",0,&Origin { request_packet_id: None, ..self.origin(ts) },self.mn_state,None),
+					}
 				}
-			}
-		} else {
+			},
 
-			assert!(packet.header.caplen>16);
-			let packet_type = PacketType::from_u8(packet.data[14]);
-			trace!("Got packet of type {:?} [{} -> {}].", packet_type, packet.data[15], packet.data[16]);
+			Some(None) => {
+				// Stored as error instead of logged, so that a misbehaving node cannot flood the log.
+				debug!("Got malformed Powerlink packet: {:?}", data);
+				let src = payload.and_then(|p| p.get(2).cloned()).unwrap_or(0);
+				self.db.insert_error("malformed_frame",src,&Origin { request_packet_id: None, ..self.origin(ts) },self.mn_state,self.cn_state[src as usize]);
+				self.last_pres = None;
+				self.process_malformed(ts);
+				self.reset_expectations();
+			},
 
-			self.process_state(packet);
+			Some(Some(frame)) => {
 
-			self.process_cyclic(packet);
+				trace!("Got packet of type {:?} [{} -> {}].", frame.packet_type(), frame.dst(), frame.src());
 
-			self.process_response(packet);
-			
-			self.reset_expectations();
+				self.process_state(&frame, ts);
+
+				self.process_flags(&frame, ts);
+
+				self.process_cyclic(&frame, ts);
+
+				self.process_pdo(&frame, ts);
+
+				self.process_sdo(&frame, ts);
+
+				self.process_nmt_command(&frame, ts);
+
+				self.process_ident(&frame, ts);
 
-			self.process_request(packet);
+				self.process_status(&frame, ts);
 
-			//self.request_ts = Some(self.get_timespec(packet));
+				self.process_response(&frame, ts);
+
+				self.process_turnaround(&frame, ts);
+				
+				self.reset_expectations();
+
+				self.process_request(&frame, ts);
+
+				if self.window.from_operational && !self.window.is_anchored() && self.all_operational() {
+					self.window.anchor(ts, self.cycle);
+				}
+
+			}
 
 		}
 
 	}
 
+	/// Returns whether the MN and all CNs seen so far are Operational.
+	fn all_operational(&self) -> bool {
+		let mut cn_states = self.cn_state[1..240].iter().filter_map(|s| *s).peekable();
+		self.mn_state == Some(NmtState::Operational)
+			&& cn_states.peek().is_some()
+			&& cn_states.all(|s| s == NmtState::Operational)
+	}
+
 	fn set_cn_state(&mut self, id: u8, state: Option<NmtState>, timestamp: Duration) {
 		if state != self.cn_state[id as usize] {
 			self.cn_state[id as usize] = state;
 			self.db.insert_state_change(id, state, timestamp, self.packet_id);
+			if let Some(pending) = self.nmt_pending[id as usize] {
+				if pending.expected.is_none() || pending.expected == state {
+					// Commands sent outside of the window have no row to link the state change to.
+					if let Some(row_id) = pending.row_id {
+						self.db.update_nmt_command(row_id, timestamp - pending.timestamp, state, self.packet_id);
+					}
+					self.nmt_pending[id as usize] = None;
+				}
+			}
 		}
 	}
 
@@ -110,109 +340,256 @@ impl<'a> Plkan<'a> {
 		}
 	}
 
-	fn process_state(&mut self, packet: &Packet) {
+	fn process_state(&mut self, frame: &Frame, ts: Timespec) {
 
-		let packet_type = PacketType::from_u8(packet.data[14]);
-		let ts = self.get_timespec(packet)-self.first_ts.unwrap();
+		let ts = ts-self.first_ts.unwrap();
 
-		match packet_type {
+		match *frame {
 			
-			Some(PacketType::SoC) => {
-				// No state transmission
-			},
-			
-			Some(PacketType::SoA) => {
-				self.set_mn_state(NmtState::from_u8(packet.data[17]), ts);
+			Frame::SoA(ref soa) => {
+				self.set_mn_state(soa.nmt_state, ts);
 			},
 
-			Some(PacketType::ASnd) => {
+			Frame::PRes(ref pres) => {
+				if pres.src==240 {
+					self.set_mn_state(pres.nmt_state, ts);
+				} else {
+					self.set_cn_state(pres.src, pres.nmt_state, ts);
+				}
+			}
+
+			_ => {
 				// No state transmission
+			}
+
+		}
+
+	}
+
+	fn process_flags(&mut self, frame: &Frame, ts: Timespec) {
+
+		let flags = frame.flags();
+
+		// The node the flags refer to.
+		let node = match *frame {
+			Frame::SoC(ref soc) => soc.src,
+			Frame::PReq(ref preq) => preq.dst,
+			Frame::PRes(ref pres) => pres.src,
+			Frame::SoA(ref soa) => soa.target,
+			Frame::ASnd(_) => return,
+		};
+
+		let rel_ts = ts-self.first_ts.unwrap();
+		self.db.insert_flags(frame.packet_type(), node, &flags, rel_ts, self.packet_id, self.mn_state, self.cn_state[node as usize]);
+
+		// A CN signals a new exception by toggling EN. The MN acknowledges it by mirroring EN in EA.
+		match *frame {
+
+			Frame::PRes(_) if node != 240 => {
+				if let Some(en) = self.exception_new[node as usize] {
+					if en != flags.en {
+						self.exception_ts[node as usize] = Some(ts);
+					}
+				}
+				self.exception_new[node as usize] = Some(flags.en);
 			},
 
-			Some(PacketType::PReq) => {
-				// No state transmission
+			_ if frame.acknowledges_exception() => {
+				if let (Some(en), Some(start)) = (self.exception_new[node as usize], self.exception_ts[node as usize]) {
+					if flags.ea == en {
+						self.db.insert_exception_handshake(node, ts - start, rel_ts);
+						self.exception_ts[node as usize] = None;
+					}
+				}
 			},
 
-			Some(PacketType::PRes) => {
-				let src = packet.data[16];
-				if src==240 {
-					self.set_mn_state(NmtState::from_u8(packet.data[17]), ts);
-				} else {
-					self.set_cn_state(src, NmtState::from_u8(packet.data[17]), ts);
+			_ => {}
+
+		}
+
+	}
+
+	fn process_cyclic(&mut self, frame: &Frame, ts: Timespec) {
+
+		if let Frame::SoC(ref soc) = *frame {
+			self.cycle += 1;
+			// The first SoC has no previous one, but its MN time is needed for the clock stats.
+			let diff = self.soc_ts.map(|soc_ts| ts - soc_ts);
+			let origin = Origin { request_packet_id: self.soc_packet_id, ..self.origin(ts) };
+			self.db.insert_soc(diff,self.mn_state,&origin,soc.net_time,soc.relative_time);
+			self.soc_ts = Some(ts);
+			self.soc_packet_id = Some(self.packet_id);
+		}
+	}
+
+	fn process_pdo(&mut self, frame: &Frame, ts: Timespec) {
+
+		if self.pdo_mapping.is_empty() {
+			return;
+		}
+
+		let (node, payload) = match *frame {
+			Frame::PReq(ref preq) => (preq.dst, preq.payload),
+			Frame::PRes(ref pres) => (pres.src, pres.payload),
+			_ => return,
+		};
+
+		let rel_ts = ts-self.first_ts.unwrap();
+		for (object, value) in self.pdo_mapping.extract(node, frame.packet_type(), payload) {
+			self.db.insert_pdo_value(self.cycle, rel_ts, object, value);
+		}
+
+	}
+
+	fn process_sdo(&mut self, frame: &Frame, ts: Timespec) {
+
+		let asnd = match *frame {
+			Frame::ASnd(ref asnd) if asnd.service() == Some(ServiceId::Sdo) => asnd,
+			_ => return,
+		};
+
+		let rel_ts = ts-self.first_ts.unwrap();
+
+		match SdoFrame::parse(asnd.payload) {
+			Some(sdo) => {
+				let object = self.sdo_tracker.track(asnd.src, asnd.dst, &sdo, ts, self.cycle, self.packet_id);
+				self.db.insert_sdo(asnd.src, asnd.dst, &sdo, object, rel_ts, self.packet_id);
+				for transfer in self.sdo_tracker.take_finished() {
+					self.db.insert_sdo_transfer(&transfer, transfer.start-self.first_ts.unwrap());
 				}
+			},
+			None => {
+				warn!("Got malformed SDO frame: {:?}", frame);
 			}
+		}
 
-			_ => {
-				// No state transmission
+	}
+
+	fn process_nmt_command(&mut self, frame: &Frame, ts: Timespec) {
+
+		let asnd = match *frame {
+			Frame::ASnd(ref asnd) if asnd.service() == Some(ServiceId::NmtCommand) && asnd.src == 240 => asnd,
+			_ => return,
+		};
+
+		let command = match NmtCommand::parse(asnd.payload) {
+			Some(command) => command,
+			None => {
+				warn!("Got malformed NMT command: {:?}", frame);
+				return;
+			}
+		};
+
+		let rel_ts = ts-self.first_ts.unwrap();
+		let known_nodes: Vec<u8> = (1..240).filter(|&n| self.cn_state[n as usize].is_some()).collect();
+
+		for node in command.targets(asnd.dst, &known_nodes) {
+			let row_id = self.db.insert_nmt_command(&command, node, rel_ts, self.packet_id);
+			if let Some(id) = command.command() {
+				if id.is_state_command() {
+					self.nmt_pending[node as usize] = Some(PendingCommand {
+						row_id: row_id,
+						expected: id.target_state(),
+						timestamp: rel_ts,
+					});
+				}
 			}
+		}
+
+	}
+
+	fn process_ident(&mut self, frame: &Frame, ts: Timespec) {
 
+		let asnd = match *frame {
+			Frame::ASnd(ref asnd) if asnd.service() == Some(ServiceId::Ident) => asnd,
+			_ => return,
+		};
+
+		match IdentResponse::parse(asnd.payload) {
+			Some(ident) => self.db.insert_ident(asnd.src, &ident, ts-self.first_ts.unwrap(), self.packet_id),
+			None => warn!("Got malformed IdentResponse: {:?}", frame),
 		}
 
 	}
 
-	fn process_cyclic(&mut self, packet: &Packet) {
+	fn process_status(&mut self, frame: &Frame, ts: Timespec) {
 
-		let packet_type = PacketType::from_u8(packet.data[14]);
-		let ts = self.get_timespec(packet);
+		let asnd = match *frame {
+			Frame::ASnd(ref asnd) if asnd.service() == Some(ServiceId::Status) => asnd,
+			_ => return,
+		};
 
-		if packet_type == Some(PacketType::SoC) {
-			if let Some(soc_ts) = self.soc_ts {
-				let diff = ts - soc_ts;
-				self.db.insert_soc(diff,self.mn_state);
+		let status = match StatusResponse::parse(asnd.payload) {
+			Some(status) => status,
+			None => {
+				warn!("Got malformed StatusResponse: {:?}", frame);
+				return;
 			}
-			self.soc_ts = Some(ts);
+		};
+
+		let rel_ts = ts-self.first_ts.unwrap();
+
+		if self.static_errors[asnd.src as usize] != Some(status.static_errors) {
+			self.static_errors[asnd.src as usize] = Some(status.static_errors);
+			self.db.insert_static_errors(asnd.src, &status, rel_ts, self.packet_id);
+		}
+
+		for entry in &status.entries {
+			self.db.insert_error_entry(asnd.src, entry, rel_ts, self.packet_id);
 		}
+
 	}
 
-	fn process_response(&mut self, packet: &Packet) {
+	fn process_response(&mut self, frame: &Frame, ts: Timespec) {
 
-		//let dest = packet.data[15];
-		let src = packet.data[16];
-		let packet_type = PacketType::from_u8(packet.data[14]);
+		let src = frame.src();
 
 		if self.request_ts.is_none() {
 			trace!("No timestamp -> no response parsing.");
 			return;
 		}
 
-		let diff = self.get_timespec(packet) - self.request_ts.unwrap();
+		let diff = ts - self.request_ts.unwrap();
+		// A missing response is often only noticed in the next cycle.
+		let origin = Origin { cycle: self.request_cycle, ..self.origin(ts) };
 
 		match self.request_type {
 			
 			Some(PacketType::PReq) => {
-				if packet_type!=Some(PacketType::PRes) || Some(src)!=self.requested_node {
+				if frame.packet_type()!=PacketType::PRes || Some(src)!=self.requested_node {
 					trace!("Missing proper PRes!");
-					self.db.insert_error("pres_missing",self.requested_node.unwrap(),self.mn_state,self.cn_state[src as usize]);
+					self.db.insert_error("pres_missing",self.requested_node.unwrap(),&origin,self.mn_state,self.cn_state[src as usize]);
 				} else {
-					self.db.insert_response("pres",src,diff,self.mn_state,self.cn_state[src as usize]);
+					self.db.insert_response("pres",src,diff,&origin,self.mn_state,self.cn_state[src as usize]);
 
 				}
 			},
 
 			Some(PacketType::SoA) => {
 
-				assert!(packet.header.caplen>17);
-				let service = ServiceId::from_u8(packet.data[17]);
+				let service = match *frame {
+					Frame::ASnd(ref asnd) => asnd.service(),
+					_ => None,
+				};
 
 				match self.request_service {
 
 					Some(ServiceId::Unspec) => {
 
-						if packet_type == Some(PacketType::ASnd) && service == Some(ServiceId::Sdo) {
+						if service == Some(ServiceId::Sdo) {
 
 							if Some(src)!=self.requested_node {
 								trace!("Got SDO from wrong node!");
-								self.db.insert_error("sdo_from_wrong_node",self.requested_node.unwrap(),self.mn_state,self.cn_state[src as usize]);
+								self.db.insert_error("sdo_from_wrong_node",self.requested_node.unwrap(),&origin,self.mn_state,self.cn_state[src as usize]);
 							} else {
-								self.db.insert_response("sdo",src,diff,self.mn_state,self.cn_state[src as usize]);
+								self.db.insert_response("sdo",src,diff,&origin,self.mn_state,self.cn_state[src as usize]);
 							}
 
 						} else {
 
-							assert!(Plkan::is_powerlink(packet));
-							let ts = self.get_timespec(packet)-self.first_ts.unwrap();
-							trace!("Got unexpected Powerlink packet after SoA: [{:?}] {:?}", ts, packet);
-							self.db.insert_error("unexpected_packet_after_soa",self.requested_node.unwrap(),self.mn_state,self.cn_state[src as usize]);
+							let ts = ts-self.first_ts.unwrap();
+							trace!("Got unexpected Powerlink packet after SoA: [{:?}] {:?}", ts, frame);
+							self.db.insert_error("unexpected_packet_after_soa",self.requested_node.unwrap(),&origin,self.mn_state,self.cn_state[src as usize]);
 
 						}
 
@@ -220,21 +597,20 @@ impl<'a> Plkan<'a> {
 
 					Some(ServiceId::NmtCommand) => {
 
-						if packet_type == Some(PacketType::ASnd) && service == Some(ServiceId::NmtCommand) {
+						if service == Some(ServiceId::NmtCommand) {
 
 							if Some(src)!=self.requested_node {
 								warn!("Got NMT command from wrong node!");
-								self.db.insert_error("nmt_from_wrong_node",self.requested_node.unwrap(),self.mn_state,self.cn_state[src as usize]);
+								self.db.insert_error("nmt_from_wrong_node",self.requested_node.unwrap(),&origin,self.mn_state,self.cn_state[src as usize]);
 							} else {
-								self.db.insert_response("nmt_command",src,diff,self.mn_state,self.cn_state[src as usize]);
+								self.db.insert_response("nmt_command",src,diff,&origin,self.mn_state,self.cn_state[src as usize]);
 							}
 
 						} else {
 
-							assert!(Plkan::is_powerlink(packet));
-							let ts = self.get_timespec(packet)-self.first_ts.unwrap();
-							trace!("Got unexpected Powerlink packet after SoA: [{:?}] {:?}", ts, packet);
-							self.db.insert_error("unexpected_packet_after_soa",self.requested_node.unwrap(),self.mn_state,self.cn_state[src as usize]);
+							let ts = ts-self.first_ts.unwrap();
+							trace!("Got unexpected Powerlink packet after SoA: [{:?}] {:?}", ts, frame);
+							self.db.insert_error("unexpected_packet_after_soa",self.requested_node.unwrap(),&origin,self.mn_state,self.cn_state[src as usize]);
 
 						}
 
@@ -243,18 +619,18 @@ impl<'a> Plkan<'a> {
 					Some(ServiceId::Ident) => {
 						if service!=Some(ServiceId::Ident) || Some(src)!=self.requested_node {
 							trace!("Missing proper Ident Response!");
-							self.db.insert_error("ident_response_missing",self.requested_node.unwrap(),self.mn_state,self.cn_state[src as usize]);
+							self.db.insert_error("ident_response_missing",self.requested_node.unwrap(),&origin,self.mn_state,self.cn_state[src as usize]);
 						} else {
-							self.db.insert_response("ident",src,diff,self.mn_state,self.cn_state[src as usize]);
+							self.db.insert_response("ident",src,diff,&origin,self.mn_state,self.cn_state[src as usize]);
 						}
 					},
 
 					Some(ServiceId::Status) => {
 						if service!=Some(ServiceId::Status) || Some(src)!=self.requested_node {
 							trace!("Missing proper Status Response!");
-							self.db.insert_error("status_response_missing",self.requested_node.unwrap(),self.mn_state,self.cn_state[src as usize]);
+							self.db.insert_error("status_response_missing",self.requested_node.unwrap(),&origin,self.mn_state,self.cn_state[src as usize]);
 						} else {
-							self.db.insert_response("status",src,diff,self.mn_state,self.cn_state[src as usize]);
+							self.db.insert_response("status",src,diff,&origin,self.mn_state,self.cn_state[src as usize]);
 						}
 					},
 
@@ -273,29 +649,73 @@ impl<'a> Plkan<'a> {
 
 	}
 
-	fn process_request(&mut self, packet: &Packet,) {
-		
-		let dest = packet.data[15];
-		//let src = packet.data[16];
-		let packet_type = PacketType::from_u8(packet.data[14]);
+	/// Measures the MN turnaround from a CN PRes to the next frame of the MN: a PReq, its own
+	/// PRes (PResMN) or the SoA. Any other frame in between, including non-POWERLINK and
+	/// malformed frames, discards the PRes (see `process_packet`).
+	fn process_turnaround(&mut self, frame: &Frame, ts: Timespec) {
+
+		let last_pres = self.last_pres.take();
+
+		match *frame {
+
+			Frame::PRes(ref pres) if pres.src != 240 => {
+				self.last_pres = Some((pres.src, ts, self.packet_id));
+			},
+
+			Frame::PReq(_) | Frame::PRes(_) | Frame::SoA(_) => {
+				if let Some((node, pres_ts, pres_packet_id)) = last_pres {
+					let origin = Origin { request_packet_id: Some(pres_packet_id), ..self.origin(ts) };
+					self.db.insert_response("mn_turnaround",node,ts-pres_ts,&origin,self.mn_state,self.cn_state[node as usize]);
+				}
+			},
+
+			_ => {}
+
+		}
+
+	}
+
+	/// A malformed frame cannot be the expected response, so the outstanding request gets
+	/// the error that any other wrong frame would cause.
+	fn process_malformed(&mut self, ts: Timespec) {
+
+		if self.request_ts.is_none() {
+			return;
+		}
+
+		let node = match self.requested_node {
+			Some(node) => node,
+			None => return,
+		};
 
-		match packet_type {
+		let error = match (self.request_type, self.request_service) {
+			(Some(PacketType::PReq), _) => "pres_missing",
+			(Some(PacketType::SoA), Some(ServiceId::Ident)) => "ident_response_missing",
+			(Some(PacketType::SoA), Some(ServiceId::Status)) => "status_response_missing",
+			(Some(PacketType::SoA), Some(ServiceId::Unspec)) | (Some(PacketType::SoA), Some(ServiceId::NmtCommand)) => "unexpected_packet_after_soa",
+			_ => return,
+		};
+
+		let origin = Origin { cycle: self.request_cycle, ..self.origin(ts) };
+		self.db.insert_error(error,node,&origin,self.mn_state,self.cn_state[node as usize]);
+
+	}
+
+	fn process_request(&mut self, frame: &Frame, ts: Timespec) {
+		
+		match *frame {
 			
-			Some(PacketType::PReq) => {
+			Frame::PReq(ref preq) => {
 				self.request_type = Some(PacketType::PReq);
-				self.requested_node = Some(dest);
-				self.request_ts = Some(self.get_timespec(packet));
+				self.requested_node = Some(preq.dst);
+				self.request_ts = Some(ts);
+				self.request_packet_id = Some(self.packet_id);
+				self.request_cycle = self.cycle;
 			},
 
-			Some(PacketType::SoA) => {
-						
-				assert!(packet.header.caplen>21);
-				let service_id = packet.data[20];
-				let target = packet.data[21];
-				let service = ServiceId::from_u8(service_id);
-
+			Frame::SoA(ref soa) => {
 
-				if let Some(service) = service {
+				if let Some(service) = soa.service() {
 					
 					if service == ServiceId::NoService {
 						trace!("No Service -> no expectations.");
@@ -307,13 +727,15 @@ impl<'a> Plkan<'a> {
 						} else {
 							Some(service)
 						};
-						self.requested_node = Some(target);
-						self.request_ts = Some(self.get_timespec(packet));
+						self.requested_node = Some(soa.target);
+						self.request_ts = Some(ts);
+						self.request_packet_id = Some(self.packet_id);
+						self.request_cycle = self.cycle;
 
 					}
 
 				} else {
-					warn!("Unknown requested service ID: {}\n{:?}", service_id, packet);
+					warn!("Unknown requested service ID: {}\n{:?}", soa.service_id, frame);
 				}
 
 			},
@@ -326,19 +748,185 @@ impl<'a> Plkan<'a> {
 
 	}
 
+	/// Closes the current cycle at a SoC and starts the next one, or adds the packet to
+	/// the current cycle. `frame` is `None` for non-POWERLINK packets.
+	fn track_cycle(&mut self, frame: Option<&Frame>, ts: Timespec) {
+		if let Some(&Frame::SoC(_)) = frame {
+			if let Some(mut cycle) = self.current_cycle.take() {
+				cycle.finish(ts);
+				// The window applies to the cycle as a whole.
+				if !self.replaying && (!self.window.is_set() || self.window.contains(cycle.soc_ts, cycle.number)) {
+					self.db.insert_cycle(&cycle, cycle.soc_ts - self.first_ts.unwrap());
+				}
+			}
+			self.current_cycle = Some(Cycle::new(self.cycle + 1, self.mn_state, ts, self.packet_id));
+		} else if let Some(ref mut cycle) = self.current_cycle {
+			cycle.add_frame(frame, ts, self.packet_id);
+		}
+	}
+
+	/// Describes the current frame. The request is the last PReq or SoA.
+	fn origin(&self, ts: Timespec) -> Origin {
+		Origin {
+			capture_time: ts,
+			timestamp: ts - self.first_ts.unwrap(),
+			cycle: self.cycle,
+			request_packet_id: self.request_packet_id,
+			packet_id: self.packet_id,
+		}
+	}
+
 	fn reset_expectations(&mut self) {
 		self.request_type = None;
 		self.request_service = None;
 		self.requested_node = None;
 	}
 
-	fn get_timespec(&self, packet: &Packet) -> Timespec {
-		Timespec {sec: packet.header.ts.tv_sec, nsec: packet.header.ts.tv_usec as i32}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+
+	/// Wraps the POWERLINK part of a frame into an Ethernet frame.
+	fn ethernet(powerlink: &[u8]) -> Vec<u8> {
+		let mut data = vec![0x01, 0x11, 0x1E, 0, 0, 0x01, 0, 0x60, 0x65, 0, 0, 0xF0];
+		data.extend_from_slice(&ETHERTYPE_POWERLINK.to_be_bytes());
+		data.extend_from_slice(powerlink);
+		data
 	}
 
-	fn is_powerlink(packet: &Packet) -> bool {
-		packet.header.caplen>=17 && packet.data[12]==0x88 && packet.data[13]==0xab
+	fn soc() -> Vec<u8> {
+		let mut data = vec![PacketType::SoC as u8, 255, 240];
+		data.resize(22, 0);
+		ethernet(&data)
 	}
 
-}
+	fn preq(dst: u8) -> Vec<u8> {
+		ethernet(&[PacketType::PReq as u8, dst, 240, 0, 0, 0, 0, 0, 0, 0])
+	}
+
+	fn pres(src: u8) -> Vec<u8> {
+		pres_in(src, NmtState::Operational)
+	}
 
+	fn pres_in(src: u8, state: NmtState) -> Vec<u8> {
+		ethernet(&[PacketType::PRes as u8, 255, src, state as u8, 0, 0, 0, 0, 0, 0])
+	}
+
+	fn nmt_command(id: NmtCommandId, dst: u8) -> Vec<u8> {
+		ethernet(&[PacketType::ASnd as u8, dst, 240, ServiceId::NmtCommand as u8, id as u8, 0])
+	}
+
+	fn soa(service: ServiceId, target: u8) -> Vec<u8> {
+		ethernet(&[PacketType::SoA as u8, 255, 240, NmtState::Operational as u8, 0, 0, service as u8, target, 0x20])
+	}
+
+	fn ipv4() -> Vec<u8> {
+		let mut data = vec![0xFF; 12];
+		data.extend_from_slice(&[0x08, 0x00]);
+		data.resize(60, 0);
+		data
+	}
+
+	/// Analyzes frames given with their capture time in microseconds.
+	fn analyze(frames: &[(i32, Vec<u8>)]) -> Database {
+		analyze_in(Window::default(), frames)
+	}
+
+	fn analyze_in(window: Window, frames: &[(i32, Vec<u8>)]) -> Database {
+		let mut db = Database::new();
+		{
+			let mut plkan = Plkan::new(&mut db);
+			plkan.set_window(window);
+			for &(us, ref data) in frames {
+				plkan.process_packet(LinkType::Ethernet, Timespec::new(0, us * 1000), data);
+			}
+			plkan.finish();
+		}
+		db
+	}
+
+	fn errors(db: &Database) -> Vec<(String,u8,usize)> {
+		db.get_errors().into_iter().map(|e| (e.error_type, e.node_id, e.count)).collect()
+	}
+
+	#[test]
+	fn malformed_frame_closes_request() {
+		let malformed = ethernet(&[0x02, 255, 1, 0, 0, 0]);
+		let db = analyze(&[(0, soc()), (10, preq(1)), (20, malformed), (30, pres(1))]);
+		let mut errors = errors(&db);
+		errors.sort();
+		assert_eq!(errors, vec![("malformed_frame".to_owned(), 1, 1), ("pres_missing".to_owned(), 1, 1)]);
+		// The PRes after the malformed frame is no response to the PReq.
+		assert!(db.get_raw("type=='pres'", false).is_empty());
+	}
+
+	#[test]
+	fn pres_is_response_to_preq() {
+		let db = analyze(&[(0, soc()), (10, preq(1)), (25, pres(1)), (40, soa(ServiceId::NoService, 0))]);
+		assert!(errors(&db).is_empty());
+		let responses = db.get_raw("type=='pres'", false);
+		assert_eq!(responses.len(), 1);
+		assert_eq!((responses[0].node_id, responses[0].timediff_ns), (1, 15_000));
+		assert_eq!((responses[0].origin.request_packet_id, responses[0].origin.packet_id), (Some(2), 3));
+	}
+
+	/// Returns node, time difference in microseconds, PRes packet ID and packet ID of all MN
+	/// turnarounds.
+	fn turnarounds(db: &Database) -> Vec<(u8,u64,Option<usize>,usize)> {
+		db.get_raw("type=='mn_turnaround'", false).into_iter()
+			.map(|r| (r.node_id, r.timediff_ns / 1000, r.origin.request_packet_id, r.origin.packet_id))
+			.collect()
+	}
+
+	#[test]
+	fn turnaround_to_preq() {
+		let db = analyze(&[(0, soc()), (10, preq(1)), (20, pres(1)), (23, preq(2)), (30, pres(2)), (34, soa(ServiceId::NoService, 0))]);
+		assert_eq!(turnarounds(&db), vec![(1, 3, Some(3), 4), (2, 4, Some(5), 6)]);
+	}
+
+	#[test]
+	fn turnaround_to_pres_mn() {
+		let db = analyze(&[(0, soc()), (10, preq(1)), (20, pres(1)), (25, pres(240)), (30, soa(ServiceId::NoService, 0))]);
+		// The PResMN itself starts no turnaround.
+		assert_eq!(turnarounds(&db), vec![(1, 5, Some(3), 4)]);
+	}
+
+	#[test]
+	fn turnaround_to_soa() {
+		let db = analyze(&[(0, soc()), (10, preq(1)), (20, pres(1)), (26, soa(ServiceId::Status, 1))]);
+		assert_eq!(turnarounds(&db), vec![(1, 6, Some(3), 4)]);
+	}
+
+	#[test]
+	fn turnaround_discarded_by_other_frames() {
+		// A non-POWERLINK frame after the PRes
+		let db = analyze(&[(0, soc()), (10, preq(1)), (20, pres(1)), (22, ipv4()), (30, soa(ServiceId::NoService, 0))]);
+		assert!(turnarounds(&db).is_empty());
+
+		// A malformed frame after the PRes
+		let malformed = ethernet(&[0x02, 255, 1, 0, 0, 0]);
+		let db = analyze(&[(0, soc()), (10, preq(1)), (20, pres(1)), (22, malformed), (30, soa(ServiceId::NoService, 0))]);
+		assert!(turnarounds(&db).is_empty());
+
+		// The PRes of another CN without PReq replaces the first one.
+		let db = analyze(&[(0, soc()), (10, preq(1)), (20, pres(1)), (24, pres(2)), (30, soa(ServiceId::NoService, 0))]);
+		assert_eq!(turnarounds(&db), vec![(2, 6, Some(4), 5)]);
+	}
+
+	#[test]
+	fn nmt_command_before_window_is_not_linked() {
+		let mut window = Window::default();
+		window.set_cycles("2:").unwrap();
+		let db = analyze_in(window, &[
+			(0, soc()), (10, pres_in(1, NmtState::PreOperational2)), (20, nmt_command(NmtCommandId::NmtStartNode, 1)),
+			(100, soc()), (110, pres_in(1, NmtState::Operational)), (120, nmt_command(NmtCommandId::NmtStopNode, 1)),
+			(200, soc()), (210, pres_in(1, NmtState::Stopped)),
+		]);
+		let commands: Vec<_> = db.get_nmt_commands().into_iter().map(|c| (c.command, c.delay_ns, c.state)).collect();
+		assert_eq!(commands, vec![("NmtStopNode".to_owned(), Some(90_000), Some(NmtState::Stopped))]);
+	}
+
+}
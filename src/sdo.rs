@@ -0,0 +1,588 @@
+// Powerlink Analyzer - Analyze Ethernet POWERLINK Network Traffic
+// Copyright (C) 2016, Thomas Keh
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, version 3 of the License.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The SDO module decodes the asynchronous sequence layer and the command layer of SDO frames
+//! transported via ASnd.
+
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use time::Timespec;
+use num::FromPrimitive;
+use frame::*;
+
+/// Index and subindex of an object dictionary entry.
+pub type ObjectId = (u16,u8);
+
+const SEQUENCE_LAYER_LEN: usize = 4;
+const COMMAND_LAYER_LEN: usize = 8;
+
+enum_from_primitive! {
+	#[derive(Debug, PartialEq, Clone, Copy)]
+	#[repr(u8)]
+	pub enum ReceiveCon {
+		NoConnection = 0,
+		Initialization = 1,
+		ConnectionValid = 2,
+		ErrorResponse = 3,
+	}
+}
+
+enum_from_primitive! {
+	#[derive(Debug, PartialEq, Clone, Copy)]
+	#[repr(u8)]
+	pub enum SendCon {
+		NoConnection = 0,
+		Initialization = 1,
+		ConnectionValid = 2,
+		ConnectionValidAckRequest = 3,
+	}
+}
+
+enum_from_primitive! {
+	#[derive(Debug, PartialEq, Clone, Copy)]
+	#[repr(u8)]
+	pub enum Segmentation {
+		Expedited = 0,
+		Initiate = 1,
+		Segment = 2,
+		Complete = 3,
+	}
+}
+
+enum_from_primitive! {
+	#[derive(Debug, PartialEq, Clone, Copy)]
+	#[repr(u8)]
+	pub enum SdoCommand {
+		Nil = 0x00,
+		WriteByIndex = 0x01,
+		ReadByIndex = 0x02,
+		WriteAllByIndex = 0x03,
+		ReadAllByIndex = 0x04,
+		WriteByName = 0x05,
+		ReadByName = 0x06,
+		FileWrite = 0x20,
+		FileRead = 0x21,
+		WriteMultipleParamByIndex = 0x31,
+		ReadMultipleParamByIndex = 0x32,
+		MaxSegmentSize = 0x70,
+	}
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SequenceLayer {
+	pub receive_seq: u8,
+	pub receive_con: ReceiveCon,
+	pub send_seq: u8,
+	pub send_con: SendCon,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CommandLayer<'a> {
+	pub transaction_id: u8,
+	pub response: bool,
+	pub abort: bool,
+	pub segmentation: Segmentation,
+	pub command_id: u8,
+	pub segment_size: u16,
+	/// Total size of a segmented transfer (initiate frames only).
+	pub data_size: Option<u32>,
+	/// Command data following the command layer header and the data size.
+	pub payload: &'a [u8],
+}
+
+impl<'a> CommandLayer<'a> {
+
+	pub fn command(&self) -> Option<SdoCommand> {
+		SdoCommand::from_u8(self.command_id)
+	}
+
+	/// Index and subindex addressed by the first frame of a request by index.
+	pub fn object(&self) -> Option<(u16,u8)> {
+		let by_index = matches!(self.command(),
+			Some(SdoCommand::WriteByIndex) | Some(SdoCommand::ReadByIndex) |
+			Some(SdoCommand::WriteAllByIndex) | Some(SdoCommand::ReadAllByIndex));
+		let first = self.segmentation == Segmentation::Expedited || self.segmentation == Segmentation::Initiate;
+		if by_index && first && !self.response && !self.abort && self.payload.len() >= 3 {
+			Some((read_u16_le(self.payload, 0), self.payload[2]))
+		} else {
+			None
+		}
+	}
+
+	/// The reason of an abort.
+	pub fn abort_code(&self) -> Option<u32> {
+		if self.abort && self.payload.len() >= 4 {
+			Some(read_u32_le(self.payload, 0))
+		} else {
+			None
+		}
+	}
+
+	/// Number of object data bytes carried by this frame.
+	pub fn object_data_size_in_frame(&self) -> usize {
+		if self.abort {
+			0
+		} else if self.object().is_some() {
+			self.payload.len().saturating_sub(4)
+		} else {
+			self.payload.len()
+		}
+	}
+
+	/// Number of object data bytes announced by this frame, i.e. without the index header of
+	/// requests by index.
+	pub fn object_data_size(&self) -> u32 {
+		let size = self.data_size.unwrap_or(self.segment_size as u32);
+		if self.object().is_some() {
+			size.saturating_sub(4)
+		} else {
+			size
+		}
+	}
+
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SdoFrame<'a> {
+	pub sequence: SequenceLayer,
+	/// `None` for pure sequence layer frames such as acknowledges and connection setup.
+	pub command: Option<CommandLayer<'a>>,
+}
+
+impl<'a> SdoFrame<'a> {
+
+	/// Decodes the payload of an ASnd SDO frame.
+	pub fn parse(payload: &'a [u8]) -> Option<SdoFrame<'a>> {
+
+		if payload.len() < SEQUENCE_LAYER_LEN {
+			return None;
+		}
+
+		let sequence = SequenceLayer {
+			receive_seq: payload[0] >> 2,
+			receive_con: ReceiveCon::from_u8(payload[0] & 0x03).unwrap(),
+			send_seq: payload[1] >> 2,
+			send_con: SendCon::from_u8(payload[1] & 0x03).unwrap(),
+		};
+
+		let data = &payload[SEQUENCE_LAYER_LEN..];
+		let command = if data.len() < COMMAND_LAYER_LEN {
+			None
+		} else {
+			let segmentation = Segmentation::from_u8((data[2] >> 4) & 0x03).unwrap();
+			let segment_size = read_u16_le(data, 4);
+			let (data_size, offset) = if segmentation == Segmentation::Initiate && data.len() >= COMMAND_LAYER_LEN + 4 {
+				(Some(read_u32_le(data, COMMAND_LAYER_LEN)), COMMAND_LAYER_LEN + 4)
+			} else {
+				(None, COMMAND_LAYER_LEN)
+			};
+			let end = ::std::cmp::min(data.len(), COMMAND_LAYER_LEN + segment_size as usize);
+			let command = CommandLayer {
+				transaction_id: data[1],
+				response: data[2] & 0x80 != 0,
+				abort: data[2] & 0x40 != 0,
+				segmentation,
+				command_id: data[3],
+				segment_size,
+				data_size,
+				payload: if offset <= end { &data[offset..end] } else { &[] },
+			};
+			// Frames are padded to the minimum Ethernet size, so a pure sequence layer
+			// frame shows up as an empty NIL command.
+			if command.command_id == 0 && !command.response && !command.abort && segment_size == 0 {
+				None
+			} else {
+				Some(command)
+			}
+		};
+
+		Some(SdoFrame {
+			sequence,
+			command,
+		})
+	}
+
+}
+
+/// Returns the standard description of an SDO abort code.
+pub fn abort_text(code: u32) -> &'static str {
+	match code {
+		0x0504_0000 => "SDO protocol timed out",
+		0x0504_0001 => "Client/server command ID not valid or unknown",
+		0x0504_0002 => "Invalid block size",
+		0x0504_0003 => "Invalid sequence number",
+		0x0504_0005 => "Out of memory",
+		0x0601_0000 => "Unsupported access to an object",
+		0x0601_0001 => "Attempt to read a write-only object",
+		0x0601_0002 => "Attempt to write a read-only object",
+		0x0602_0000 => "Object does not exist in the object dictionary",
+		0x0604_0041 => "Object cannot be mapped to the PDO",
+		0x0604_0042 => "Number and length of the mapped objects would exceed the PDO length",
+		0x0604_0043 => "General parameter incompatibility",
+		0x0604_0047 => "General internal incompatibility in the device",
+		0x0606_0000 => "Access failed due to a hardware error",
+		0x0607_0010 => "Data type does not match, length of service parameter does not match",
+		0x0607_0012 => "Data type does not match, length of service parameter too high",
+		0x0607_0013 => "Data type does not match, length of service parameter too low",
+		0x0609_0011 => "Subindex does not exist",
+		0x0609_0030 => "Value range of parameter exceeded",
+		0x0609_0031 => "Value of parameter written too high",
+		0x0609_0032 => "Value of parameter written too low",
+		0x0609_0036 => "Maximum value is less than minimum value",
+		0x0800_0000 => "General error",
+		0x0800_0020 => "Data cannot be transferred or stored to the application",
+		0x0800_0021 => "Data cannot be transferred or stored to the application because of local control",
+		0x0800_0022 => "Data cannot be transferred or stored to the application because of the present device state",
+		0x0800_0023 => "Object dictionary dynamic generation failed or no object dictionary is present",
+		0x0800_0024 => "EDS, DCF or Concise DCF data set empty",
+		_ => "Unknown abort code",
+	}
+}
+
+/// A logical SDO transfer joined from all frames of one transaction.
+#[derive(Debug, Clone)]
+pub struct SdoTransfer {
+	pub client: u8,
+	pub server: u8,
+	pub transaction_id: u8,
+	pub command_id: u8,
+	pub object: Option<(u16,u8)>,
+	pub start: Timespec,
+	pub end: Timespec,
+	pub start_packet_id: usize,
+	/// Number of distinct POWERLINK cycles which carried a frame of the transfer.
+	pub cycles: usize,
+	pub frames: usize,
+	/// Object data bytes moved in either direction.
+	pub bytes: u64,
+	/// Data size announced by the initiate frame of a segmented transfer.
+	pub data_size: Option<u32>,
+	pub retransmissions: usize,
+	pub result: TransferResult,
+	pub abort_code: Option<u32>,
+	last_cycle: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransferResult {
+	Ok,
+	Abort,
+	/// The transfer was superseded by a new transfer with the same transaction ID, or was
+	/// still open at the end of the capture.
+	Incomplete,
+}
+
+impl SdoTransfer {
+
+	pub fn command(&self) -> Option<SdoCommand> {
+		SdoCommand::from_u8(self.command_id)
+	}
+
+	fn add_frame(&mut self, ts: Timespec, cycle: u64) {
+		self.end = ts;
+		self.frames += 1;
+		if self.last_cycle != Some(cycle) {
+			self.cycles += 1;
+			self.last_cycle = Some(cycle);
+		}
+	}
+
+}
+
+/// Joins SDO frames into transfers. Transfers are identified by client, server and
+/// transaction ID; the client is the node which sent the first request.
+#[derive(Default)]
+pub struct SdoTracker {
+	transfers: HashMap<(u8,u8,u8),SdoTransfer>,
+	last_send_seq: HashMap<(u8,u8),u8>,
+	finished: Vec<SdoTransfer>,
+}
+
+impl SdoTracker {
+
+	/// Adds a frame from `src` to `dst` to its transfer and returns the object the frame
+	/// refers to, if known.
+	pub fn track(&mut self, src: u8, dst: u8, sdo: &SdoFrame, ts: Timespec, cycle: u64, packet_id: usize) -> Option<(u16,u8)> {
+
+		// A repeated send sequence number in the same direction is a retransmission.
+		let retransmission = sdo.command.is_some()
+			&& sdo.sequence.send_con != SendCon::NoConnection
+			&& self.last_send_seq.insert((src, dst), sdo.sequence.send_seq) == Some(sdo.sequence.send_seq);
+
+		let command = match sdo.command {
+			Some(ref command) => command,
+			None => return None,
+		};
+
+		let key = if command.response {
+			(dst, src, command.transaction_id)
+		} else {
+			(src, dst, command.transaction_id)
+		};
+
+		let first = command.segmentation == Segmentation::Expedited || command.segmentation == Segmentation::Initiate;
+		if !command.response && !command.abort && first && !retransmission {
+			if let Some(mut old) = self.transfers.remove(&key) {
+				old.result = TransferResult::Incomplete;
+				self.finished.push(old);
+			}
+			self.transfers.insert(key, SdoTransfer {
+				client: src,
+				server: dst,
+				transaction_id: command.transaction_id,
+				command_id: command.command_id,
+				object: command.object(),
+				start: ts,
+				end: ts,
+				start_packet_id: packet_id,
+				cycles: 0,
+				frames: 0,
+				bytes: 0,
+				data_size: command.data_size,
+				retransmissions: 0,
+				result: TransferResult::Ok,
+				abort_code: None,
+				last_cycle: None,
+			});
+		}
+
+		let done = {
+			let transfer = self.transfers.get_mut(&key)?;
+
+			transfer.add_frame(ts, cycle);
+
+			if retransmission {
+				transfer.retransmissions += 1;
+			} else {
+				transfer.bytes += command.object_data_size_in_frame() as u64;
+				if command.response && command.data_size.is_some() {
+					transfer.data_size = command.data_size;
+				}
+			}
+
+			if command.abort {
+				transfer.result = TransferResult::Abort;
+				transfer.abort_code = command.abort_code();
+			}
+
+			command.abort || (command.response && (command.segmentation == Segmentation::Expedited || command.segmentation == Segmentation::Complete))
+		};
+
+		let object = self.transfers[&key].object;
+		if done {
+			self.finished.push(self.transfers.remove(&key).unwrap());
+		}
+		object
+	}
+
+	/// Returns all transfers which were finished since the last call.
+	pub fn take_finished(&mut self) -> Vec<SdoTransfer> {
+		::std::mem::take(&mut self.finished)
+	}
+
+	/// Ends all open transfers as incomplete, e.g. at the end of the capture, and returns
+	/// them together with the finished ones.
+	pub fn finish_all(&mut self) -> Vec<SdoTransfer> {
+		let mut open: Vec<SdoTransfer> = self.transfers.drain().map(|(_, transfer)| transfer).collect();
+		open.sort_by_key(|transfer| transfer.start_packet_id);
+		for mut transfer in open {
+			transfer.result = TransferResult::Incomplete;
+			self.finished.push(transfer);
+		}
+		self.take_finished()
+	}
+
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+
+	const RESPONSE: u8 = 0x80;
+	const ABORT: u8 = 0x40;
+
+	/// Builds an SDO payload with an established connection. `flags` holds the response and
+	/// abort bits, the segmentation is added to them.
+	fn frame(send_seq: u8, transaction_id: u8, flags: u8, segmentation: Segmentation, command: SdoCommand, data: &[u8]) -> Vec<u8> {
+		let mut frame = vec![
+			(send_seq << 2) | ReceiveCon::ConnectionValid as u8,
+			(send_seq << 2) | SendCon::ConnectionValid as u8,
+			0, 0,
+			0, transaction_id, flags | ((segmentation as u8) << 4), command as u8,
+		];
+		frame.extend_from_slice(&(data.len() as u16).to_le_bytes());
+		frame.extend_from_slice(&[0, 0]);
+		frame.extend_from_slice(data);
+		frame
+	}
+
+	fn read_request(send_seq: u8, transaction_id: u8) -> Vec<u8> {
+		frame(send_seq, transaction_id, 0, Segmentation::Expedited, SdoCommand::ReadByIndex, &[0x18, 0x10, 0x01, 0x00])
+	}
+
+	fn track(tracker: &mut SdoTracker, src: u8, dst: u8, payload: &[u8], cycle: u64, packet_id: usize) -> Option<ObjectId> {
+		let sdo = SdoFrame::parse(payload).unwrap();
+		tracker.track(src, dst, &sdo, Timespec::new(cycle as i64, 0), cycle, packet_id)
+	}
+
+	#[test]
+	fn parse_expedited_request() {
+		let payload = read_request(5, 7);
+		let sdo = SdoFrame::parse(&payload).unwrap();
+		assert_eq!(sdo.sequence.send_seq, 5);
+		assert_eq!(sdo.sequence.send_con, SendCon::ConnectionValid);
+		assert_eq!(sdo.sequence.receive_con, ReceiveCon::ConnectionValid);
+		let command = sdo.command.unwrap();
+		assert_eq!(command.transaction_id, 7);
+		assert!(!command.response);
+		assert_eq!(command.segmentation, Segmentation::Expedited);
+		assert_eq!(command.command(), Some(SdoCommand::ReadByIndex));
+		assert_eq!(command.object(), Some((0x1018, 1)));
+		assert_eq!(command.object_data_size_in_frame(), 0);
+		assert_eq!(command.object_data_size(), 0);
+	}
+
+	#[test]
+	fn parse_initiate_with_data_size() {
+		let mut data = vec![100, 0, 0, 0, 0x00, 0x20, 0x00, 0x00];
+		data.extend_from_slice(&[0xAA; 10]);
+		let payload = frame(1, 2, 0, Segmentation::Initiate, SdoCommand::WriteByIndex, &data);
+		let command = SdoFrame::parse(&payload).unwrap().command.unwrap();
+		assert_eq!(command.data_size, Some(100));
+		assert_eq!(command.object(), Some((0x2000, 0)));
+		assert_eq!(command.object_data_size_in_frame(), 10);
+		assert_eq!(command.object_data_size(), 96);
+	}
+
+	#[test]
+	fn parse_sequence_layer_only() {
+		// A connection setup padded with zeros to the minimum frame size
+		let mut payload = vec![0x01, 0x01, 0, 0];
+		payload.extend_from_slice(&[0; 40]);
+		let sdo = SdoFrame::parse(&payload).unwrap();
+		assert_eq!(sdo.sequence.receive_con, ReceiveCon::Initialization);
+		assert_eq!(sdo.sequence.send_con, SendCon::Initialization);
+		assert!(sdo.command.is_none());
+
+		assert!(SdoFrame::parse(&[0, 0, 0]).is_none());
+		assert!(SdoFrame::parse(&[0x02, 0x02, 0, 0, 0, 1, 0]).unwrap().command.is_none());
+	}
+
+	#[test]
+	fn parse_abort() {
+		let payload = frame(3, 4, RESPONSE | ABORT, Segmentation::Expedited, SdoCommand::ReadByIndex, &0x0602_0000u32.to_le_bytes());
+		let command = SdoFrame::parse(&payload).unwrap().command.unwrap();
+		assert!(command.abort);
+		assert_eq!(command.abort_code(), Some(0x0602_0000));
+		assert_eq!(abort_text(0x0602_0000), "Object does not exist in the object dictionary");
+		assert_eq!(command.object(), None);
+		assert_eq!(command.object_data_size_in_frame(), 0);
+	}
+
+	#[test]
+	fn track_expedited_transfer() {
+		let mut tracker = SdoTracker::default();
+		assert_eq!(track(&mut tracker, 240, 1, &read_request(1, 9), 10, 100), Some((0x1018, 1)));
+		assert!(tracker.take_finished().is_empty());
+
+		let response = frame(1, 9, RESPONSE, Segmentation::Expedited, SdoCommand::ReadByIndex, &[1, 2, 3, 4]);
+		assert_eq!(track(&mut tracker, 1, 240, &response, 11, 110), Some((0x1018, 1)));
+
+		let finished = tracker.take_finished();
+		assert_eq!(finished.len(), 1);
+		let transfer = &finished[0];
+		assert_eq!((transfer.client, transfer.server, transfer.transaction_id), (240, 1, 9));
+		assert_eq!(transfer.command(), Some(SdoCommand::ReadByIndex));
+		assert_eq!(transfer.start, Timespec::new(10, 0));
+		assert_eq!(transfer.end, Timespec::new(11, 0));
+		assert_eq!(transfer.start_packet_id, 100);
+		assert_eq!(transfer.frames, 2);
+		assert_eq!(transfer.cycles, 2);
+		assert_eq!(transfer.bytes, 4);
+		assert_eq!(transfer.result, TransferResult::Ok);
+	}
+
+	#[test]
+	fn track_transfer_before_first_cycle() {
+		// Frames before the first SoC belong to cycle 0.
+		let mut tracker = SdoTracker::default();
+		track(&mut tracker, 240, 1, &read_request(1, 9), 0, 1);
+		let response = frame(1, 9, RESPONSE, Segmentation::Expedited, SdoCommand::ReadByIndex, &[1, 2, 3, 4]);
+		track(&mut tracker, 1, 240, &response, 0, 2);
+
+		let finished = tracker.take_finished();
+		assert_eq!(finished[0].frames, 2);
+		assert_eq!(finished[0].cycles, 1);
+	}
+
+	#[test]
+	fn track_segmented_transfer_with_retransmission() {
+		let mut tracker = SdoTracker::default();
+		let mut initiate = vec![12, 0, 0, 0, 0x00, 0x20, 0x00, 0x00];
+		initiate.extend_from_slice(&[0; 4]);
+		let initiate = frame(1, 3, 0, Segmentation::Initiate, SdoCommand::WriteByIndex, &initiate);
+		let segment = frame(2, 3, 0, Segmentation::Segment, SdoCommand::WriteByIndex, &[0; 4]);
+		let complete = frame(3, 3, 0, Segmentation::Complete, SdoCommand::WriteByIndex, &[0; 4]);
+		let response = frame(1, 3, RESPONSE, Segmentation::Expedited, SdoCommand::WriteByIndex, &[]);
+
+		track(&mut tracker, 240, 2, &initiate, 1, 1);
+		track(&mut tracker, 240, 2, &segment, 2, 2);
+		track(&mut tracker, 240, 2, &segment, 2, 3);
+		track(&mut tracker, 240, 2, &complete, 3, 4);
+		assert!(tracker.take_finished().is_empty());
+		track(&mut tracker, 2, 240, &response, 3, 5);
+
+		let finished = tracker.take_finished();
+		assert_eq!(finished.len(), 1);
+		let transfer = &finished[0];
+		assert_eq!(transfer.object, Some((0x2000, 0)));
+		assert_eq!(transfer.data_size, Some(12));
+		assert_eq!(transfer.frames, 5);
+		assert_eq!(transfer.cycles, 3);
+		assert_eq!(transfer.retransmissions, 1);
+		assert_eq!(transfer.bytes, 12);
+		assert_eq!(transfer.result, TransferResult::Ok);
+	}
+
+	#[test]
+	fn track_abort_and_incomplete_transfers() {
+		let mut tracker = SdoTracker::default();
+
+		track(&mut tracker, 240, 1, &read_request(1, 1), 1, 1);
+		let abort = frame(1, 1, RESPONSE | ABORT, Segmentation::Expedited, SdoCommand::ReadByIndex, &0x0609_0011u32.to_le_bytes());
+		track(&mut tracker, 1, 240, &abort, 2, 2);
+		let finished = tracker.take_finished();
+		assert_eq!(finished[0].result, TransferResult::Abort);
+		assert_eq!(finished[0].abort_code, Some(0x0609_0011));
+
+		// A new request with the same transaction ID supersedes the open one.
+		track(&mut tracker, 240, 1, &read_request(2, 5), 3, 3);
+		track(&mut tracker, 240, 1, &read_request(3, 5), 4, 4);
+		let finished = tracker.take_finished();
+		assert_eq!(finished.len(), 1);
+		assert_eq!(finished[0].start_packet_id, 3);
+		assert_eq!(finished[0].result, TransferResult::Incomplete);
+
+		// Transfers still open at the end are returned in the order they started.
+		track(&mut tracker, 240, 2, &read_request(1, 6), 5, 5);
+		let finished = tracker.finish_all();
+		assert_eq!(finished.iter().map(|t| t.start_packet_id).collect::<Vec<_>>(), vec![4, 5]);
+		assert!(finished.iter().all(|t| t.result == TransferResult::Incomplete));
+		assert!(tracker.finish_all().is_empty());
+	}
+
+}
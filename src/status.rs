@@ -0,0 +1,194 @@
+// Powerlink Analyzer - Analyze Ethernet POWERLINK Network Traffic
+// Copyright (C) 2016, Thomas Keh
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, version 3 of the License.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The Status module decodes StatusResponse frames including their error/event entries.
+
+use num::FromPrimitive;
+use time::Timespec;
+use types::*;
+use frame::*;
+
+const STATUS_RESPONSE_LEN: usize = 14;
+const ERROR_ENTRY_LEN: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ErrorEntry {
+	pub entry_type: u16,
+	pub error_code: u16,
+	pub timestamp: Timespec,
+	pub additional_info: u64,
+}
+
+impl ErrorEntry {
+
+	/// Status entries describe the current state, history entries past errors and events.
+	pub fn is_status(&self) -> bool {
+		self.entry_type & 0x8000 != 0
+	}
+
+	pub fn mode(&self) -> &'static str {
+		match (self.entry_type >> 12) & 0x03 {
+			1 => "active",
+			2 => "cleared",
+			3 => "event",
+			_ => "-",
+		}
+	}
+
+	pub fn profile(&self) -> u16 {
+		self.entry_type & 0x0FFF
+	}
+
+}
+
+#[derive(Debug, Clone)]
+pub struct StatusResponse {
+	/// Exception new
+	pub en: bool,
+	/// Exception clear
+	pub ec: bool,
+	pub pr: u8,
+	pub rs: u8,
+	pub nmt_state: Option<NmtState>,
+	/// The first byte is the error register (object 0x1001), bytes 2-7 are device specific.
+	pub static_errors: [u8; 8],
+	pub entries: Vec<ErrorEntry>,
+}
+
+impl StatusResponse {
+
+	/// Decodes the payload of an ASnd StatusResponse frame.
+	pub fn parse(payload: &[u8]) -> Option<StatusResponse> {
+
+		if payload.len() < STATUS_RESPONSE_LEN {
+			return None;
+		}
+
+		let mut static_errors = [0u8; 8];
+		static_errors.copy_from_slice(&payload[6..14]);
+
+		// The list of entries is terminated by an entry of type 0 or by the end of the frame.
+		let mut entries = Vec::new();
+		let mut offset = STATUS_RESPONSE_LEN;
+		while offset + ERROR_ENTRY_LEN <= payload.len() {
+			let entry_type = read_u16_le(payload, offset);
+			if entry_type == 0 {
+				break;
+			}
+			entries.push(ErrorEntry {
+				entry_type,
+				error_code: read_u16_le(payload, offset+2),
+				timestamp: Timespec {
+					sec: read_u32_le(payload, offset+4) as i64,
+					nsec: read_u32_le(payload, offset+8) as i32,
+				},
+				additional_info: read_u64_le(payload, offset+12),
+			});
+			offset += ERROR_ENTRY_LEN;
+		}
+
+		Some(StatusResponse {
+			en: payload[0] & 0x20 != 0,
+			ec: payload[0] & 0x08 != 0,
+			pr: (payload[1] >> 3) & 0x07,
+			rs: payload[1] & 0x07,
+			nmt_state: NmtState::from_u8(payload[2]),
+			static_errors,
+			entries,
+		})
+	}
+
+}
+
+#[cfg(test)]
+pub mod tests {
+
+	use super::*;
+
+	/// Builds an error entry as transmitted in a StatusResponse.
+	pub fn entry(entry_type: u16, error_code: u16, sec: u32, nsec: u32, additional_info: u64) -> Vec<u8> {
+		let mut entry = Vec::new();
+		entry.extend_from_slice(&entry_type.to_le_bytes());
+		entry.extend_from_slice(&error_code.to_le_bytes());
+		entry.extend_from_slice(&sec.to_le_bytes());
+		entry.extend_from_slice(&nsec.to_le_bytes());
+		entry.extend_from_slice(&additional_info.to_le_bytes());
+		entry
+	}
+
+	/// Builds a StatusResponse payload of an operational node with EN set, PR 3 and RS 5.
+	pub fn status_response(static_errors: [u8; 8], entries: &[Vec<u8>]) -> Vec<u8> {
+		let mut payload = vec![0x20, (3 << 3) | 5, NmtState::Operational as u8, 0, 0, 0];
+		payload.extend_from_slice(&static_errors);
+		for entry in entries {
+			payload.extend_from_slice(entry);
+		}
+		payload
+	}
+
+	#[test]
+	fn parse_two_entries() {
+		let payload = status_response([0x11, 0, 0, 0, 0, 0, 0, 0x80], &[
+			entry(0x9001, 0x8611, 1000, 500, 0x0102_0304_0506_0708),
+			entry(0x3002, 0x1000, 2000, 0, 0),
+		]);
+		let status = StatusResponse::parse(&payload).unwrap();
+		assert!(status.en);
+		assert!(!status.ec);
+		assert_eq!((status.pr, status.rs), (3, 5));
+		assert_eq!(status.nmt_state, Some(NmtState::Operational));
+		assert_eq!(status.static_errors, [0x11, 0, 0, 0, 0, 0, 0, 0x80]);
+		assert_eq!(status.entries.len(), 2);
+
+		let first = status.entries[0];
+		assert!(first.is_status());
+		assert_eq!(first.mode(), "active");
+		assert_eq!(first.profile(), 1);
+		assert_eq!(first.error_code, 0x8611);
+		assert_eq!(first.timestamp, Timespec::new(1000, 500));
+		assert_eq!(first.additional_info, 0x0102_0304_0506_0708);
+
+		let second = status.entries[1];
+		assert!(!second.is_status());
+		assert_eq!(second.mode(), "event");
+		assert_eq!(second.profile(), 2);
+	}
+
+	#[test]
+	fn parse_stops_at_terminator() {
+		let payload = status_response([0; 8], &[
+			entry(0x9001, 0x8611, 1, 0, 0),
+			entry(0, 0, 0, 0, 0),
+			entry(0x9001, 0x8612, 2, 0, 0),
+		]);
+		let status = StatusResponse::parse(&payload).unwrap();
+		assert_eq!(status.entries.len(), 1);
+		assert_eq!(status.entries[0].error_code, 0x8611);
+
+		let status = StatusResponse::parse(&status_response([0; 8], &[])).unwrap();
+		assert!(status.entries.is_empty());
+	}
+
+	#[test]
+	fn parse_ignores_truncated_entry() {
+		let mut payload = status_response([0; 8], &[entry(0x9001, 0x8611, 1, 0, 0)]);
+		payload.extend_from_slice(&entry(0x9001, 0x8612, 2, 0, 0)[..ERROR_ENTRY_LEN - 1]);
+		let status = StatusResponse::parse(&payload).unwrap();
+		assert_eq!(status.entries.len(), 1);
+
+		assert!(StatusResponse::parse(&payload[..STATUS_RESPONSE_LEN - 1]).is_none());
+	}
+
+}
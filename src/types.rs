@@ -63,3 +63,102 @@ enum_from_primitive! {
 	}
 }
 
+enum_from_primitive! {
+	#[derive(Debug, PartialEq, Clone, Copy)]
+	#[repr(u8)]
+	pub enum NmtCommandId {
+		NmtStartNode = 0x21,
+		NmtStopNode = 0x22,
+		NmtEnterPreOperational2 = 0x23,
+		NmtEnableReadyToOperate = 0x24,
+		NmtResetNode = 0x28,
+		NmtResetCommunication = 0x29,
+		NmtResetConfiguration = 0x2A,
+		NmtSwReset = 0x2B,
+		NmtStartNodeEx = 0x41,
+		NmtStopNodeEx = 0x42,
+		NmtEnterPreOperational2Ex = 0x43,
+		NmtEnableReadyToOperateEx = 0x44,
+		NmtResetNodeEx = 0x48,
+		NmtResetCommunicationEx = 0x49,
+		NmtResetConfigurationEx = 0x4A,
+		NmtSwResetEx = 0x4B,
+		NmtNetHostNameSet = 0x62,
+		NmtFlushArpEntry = 0x63,
+		NmtPublishConfiguredNodes = 0x80,
+		NmtPublishActiveNodes = 0x90,
+		NmtPublishPreOperational1 = 0x91,
+		NmtPublishPreOperational2 = 0x92,
+		NmtPublishReadyToOperate = 0x93,
+		NmtPublishOperational = 0x94,
+		NmtPublishStopped = 0x95,
+		NmtPublishEmergencyNew = 0xA0,
+		NmtPublishTime = 0xB0,
+		NmtInvalidService = 0xFF,
+	}
+}
+
+impl NmtCommandId {
+
+	/// Whether the command addresses the nodes in a node list instead of the destination node.
+	pub fn is_extended(&self) -> bool {
+		(*self as u8) & 0xF0 == 0x40
+	}
+
+	/// Whether the command makes the addressed nodes change their NMT state.
+	pub fn is_state_command(&self) -> bool {
+		let id = *self as u8;
+		(0x21..=0x2B).contains(&id) || (0x41..=0x4B).contains(&id)
+	}
+
+	/// The state a node enters after the command. `None` means any state, e.g. after a reset,
+	/// or that the command is no state command.
+	pub fn target_state(&self) -> Option<NmtState> {
+		if !self.is_state_command() {
+			return None;
+		}
+		match (*self as u8) & 0x0F {
+			0x01 => Some(NmtState::Operational),
+			0x02 => Some(NmtState::Stopped),
+			0x03 => Some(NmtState::PreOperational2),
+			0x04 => Some(NmtState::ReadyToOperate),
+			_ => None,
+		}
+	}
+
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+
+	#[test]
+	fn nmt_command_target_state() {
+		for &(plain, extended, state) in &[
+			(NmtCommandId::NmtStartNode, NmtCommandId::NmtStartNodeEx, Some(NmtState::Operational)),
+			(NmtCommandId::NmtStopNode, NmtCommandId::NmtStopNodeEx, Some(NmtState::Stopped)),
+			(NmtCommandId::NmtEnterPreOperational2, NmtCommandId::NmtEnterPreOperational2Ex, Some(NmtState::PreOperational2)),
+			(NmtCommandId::NmtEnableReadyToOperate, NmtCommandId::NmtEnableReadyToOperateEx, Some(NmtState::ReadyToOperate)),
+			(NmtCommandId::NmtResetNode, NmtCommandId::NmtResetNodeEx, None),
+			(NmtCommandId::NmtResetCommunication, NmtCommandId::NmtResetCommunicationEx, None),
+			(NmtCommandId::NmtResetConfiguration, NmtCommandId::NmtResetConfigurationEx, None),
+			(NmtCommandId::NmtSwReset, NmtCommandId::NmtSwResetEx, None),
+		] {
+			assert!(plain.is_state_command() && extended.is_state_command());
+			assert!(!plain.is_extended() && extended.is_extended());
+			assert_eq!(plain.target_state(), state, "{:?}", plain);
+			assert_eq!(extended.target_state(), state, "{:?}", extended);
+		}
+	}
+
+	#[test]
+	fn nmt_services_have_no_target_state() {
+		for &id in &[NmtCommandId::NmtNetHostNameSet, NmtCommandId::NmtPublishOperational, NmtCommandId::NmtPublishPreOperational1, NmtCommandId::NmtInvalidService] {
+			assert!(!id.is_state_command(), "{:?}", id);
+			assert!(!id.is_extended(), "{:?}", id);
+			assert_eq!(id.target_state(), None, "{:?}", id);
+		}
+	}
+
+}
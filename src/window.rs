@@ -0,0 +1,225 @@
+// Powerlink Analyzer - Analyze Ethernet POWERLINK Network Traffic
+// Copyright (C) 2016, Thomas Keh
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, version 3 of the License.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The Window module restricts the statistics to a part of a capture, given by times or
+//! cycle numbers relative to an anchor.
+
+use time::{self,Duration,Timespec};
+
+/// A start or end time of the window.
+#[derive(Debug, Clone, Copy)]
+pub enum TimeBound {
+	/// Time since the anchor.
+	Relative(Duration),
+	/// Capture timestamp.
+	Absolute(Timespec),
+}
+
+impl TimeBound {
+
+	/// Parses seconds since the anchor (`12.5`), seconds since the epoch (`@1462104000.5`)
+	/// or a UTC date and time (`2016-05-01T12:00:00.5`).
+	pub fn parse(value: &str) -> Result<TimeBound, String> {
+		let invalid = || format!("Invalid time {}, expected seconds, @UNIX_SECONDS or YYYY-MM-DDTHH:MM:SS.", value);
+		if let Some(seconds) = value.strip_prefix('@') {
+			return parse_seconds(seconds).map(|d| TimeBound::Absolute(Timespec::new(0, 0) + d)).ok_or_else(invalid);
+		}
+		if let Some(seconds) = parse_seconds(value) {
+			return Ok(TimeBound::Relative(seconds));
+		}
+		let (datetime, fraction) = match value.find('.') {
+			Some(pos) => (&value[..pos], &value[pos..]),
+			None => (value, ""),
+		};
+		let tm = time::strptime(datetime, "%Y-%m-%dT%H:%M:%S")
+			.or_else(|_| time::strptime(datetime, "%Y-%m-%d %H:%M:%S"))
+			.map_err(|_| invalid())?;
+		let fraction = if fraction.is_empty() {
+			Duration::zero()
+		} else {
+			parse_seconds(&format!("0{}", fraction)).ok_or_else(invalid)?
+		};
+		Ok(TimeBound::Absolute(tm.to_timespec() + fraction))
+	}
+
+	fn resolve(&self, anchor: Timespec) -> Timespec {
+		match *self {
+			TimeBound::Relative(offset) => anchor + offset,
+			TimeBound::Absolute(ts) => ts,
+		}
+	}
+
+}
+
+/// Parses a non-negative number of seconds with up to nanosecond precision.
+fn parse_seconds(value: &str) -> Option<Duration> {
+	let (sec, fraction) = match value.find('.') {
+		Some(pos) => (&value[..pos], &value[pos+1..]),
+		None => (value, ""),
+	};
+	if sec.is_empty() || !sec.bytes().all(|b| b.is_ascii_digit()) || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+		return None;
+	}
+	let mut nanos = 0i64;
+	for (i, digit) in fraction.bytes().take(9).enumerate() {
+		nanos += (digit - b'0') as i64 * 10i64.pow(8 - i as u32);
+	}
+	Some(Duration::seconds(sec.parse().ok()?) + Duration::nanoseconds(nanos))
+}
+
+/// The part of a capture that is counted in the statistics. Packets outside of the window
+/// are still processed to keep track of the network state.
+#[derive(Debug, Clone, Default)]
+pub struct Window {
+	pub start: Option<TimeBound>,
+	pub end: Option<TimeBound>,
+	/// First and last cycle, inclusive.
+	pub first_cycle: Option<u64>,
+	pub last_cycle: Option<u64>,
+	/// Relative times and cycles count from the first time the MN and all CNs are
+	/// Operational instead of from the first packet.
+	pub from_operational: bool,
+	anchor: Option<(Timespec,u64)>,
+}
+
+impl Window {
+
+	/// Parses a cycle range `FIRST:LAST`, where either side may be omitted.
+	pub fn set_cycles(&mut self, range: &str) -> Result<(), String> {
+		let invalid = || format!("Invalid cycle range {}, expected FIRST:LAST.", range);
+		let pos = range.find(':').ok_or_else(invalid)?;
+		let parse = |s: &str| if s.is_empty() { Ok(None) } else { s.parse().map(Some).map_err(|_| invalid()) };
+		self.first_cycle = parse(&range[..pos])?;
+		self.last_cycle = parse(&range[pos+1..])?;
+		Ok(())
+	}
+
+	/// Returns whether the window restricts anything.
+	pub fn is_set(&self) -> bool {
+		self.start.is_some() || self.end.is_some() || self.first_cycle.is_some() || self.last_cycle.is_some() || self.from_operational
+	}
+
+	/// Sets the anchor at the given time and cycle unless it is set already.
+	pub fn anchor(&mut self, ts: Timespec, cycle: u64) {
+		if self.anchor.is_none() {
+			self.anchor = Some((ts, cycle));
+		}
+	}
+
+	/// Returns the time and cycle of the anchor, if set.
+	pub fn anchor_point(&self) -> Option<(Timespec,u64)> {
+		self.anchor
+	}
+
+	pub fn is_anchored(&self) -> bool {
+		self.anchor.is_some()
+	}
+
+	/// Returns whether a packet at `ts` within `cycle` is counted.
+	pub fn contains(&self, ts: Timespec, cycle: u64) -> bool {
+		let (anchor_ts, anchor_cycle) = match self.anchor {
+			Some(anchor) => anchor,
+			None => return false,
+		};
+		if ts < anchor_ts {
+			return false;
+		}
+		if let Some(start) = self.start {
+			if ts < start.resolve(anchor_ts) {
+				return false;
+			}
+		}
+		if let Some(end) = self.end {
+			if ts > end.resolve(anchor_ts) {
+				return false;
+			}
+		}
+		// Packets before the first SoC after the anchor belong to cycle 0.
+		let cycle = cycle.saturating_sub(anchor_cycle);
+		self.first_cycle.is_none_or(|first| cycle >= first) && self.last_cycle.is_none_or(|last| cycle <= last)
+	}
+
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+
+	fn relative(value: &str) -> Duration {
+		match TimeBound::parse(value) {
+			Ok(TimeBound::Relative(d)) => d,
+			other => panic!("{} parsed as {:?}", value, other),
+		}
+	}
+
+	fn absolute(value: &str) -> Timespec {
+		match TimeBound::parse(value) {
+			Ok(TimeBound::Absolute(ts)) => ts,
+			other => panic!("{} parsed as {:?}", value, other),
+		}
+	}
+
+	#[test]
+	fn parse_relative() {
+		assert_eq!(relative("12"), Duration::seconds(12));
+		assert_eq!(relative("12.5"), Duration::milliseconds(12_500));
+		assert_eq!(relative("0.000000001"), Duration::nanoseconds(1));
+		// Digits beyond nanoseconds are cut off.
+		assert_eq!(relative("1.0000000019"), Duration::nanoseconds(1_000_000_001));
+	}
+
+	#[test]
+	fn parse_absolute() {
+		assert_eq!(absolute("@1462104000.5"), Timespec::new(1_462_104_000, 500_000_000));
+		assert_eq!(absolute("2016-05-01T12:00:00"), Timespec::new(1_462_104_000, 0));
+		assert_eq!(absolute("2016-05-01 12:00:00.25"), Timespec::new(1_462_104_000, 250_000_000));
+	}
+
+	#[test]
+	fn parse_invalid() {
+		for value in &["", "-1", "1.2.3", ".5", "1e3", "@", "@x", "2016-05-01", "2016-05-01T12:00:00.x"] {
+			assert!(TimeBound::parse(value).is_err(), "{} was accepted", value);
+		}
+	}
+
+	#[test]
+	fn cycles_and_times_count_from_anchor() {
+		let mut window = Window::default();
+		assert!(!window.is_set());
+		window.set_cycles("2:3").unwrap();
+		window.end = Some(TimeBound::Relative(Duration::seconds(10)));
+		assert!(window.is_set());
+
+		let anchor = Timespec::new(100, 0);
+		assert!(!window.contains(anchor, 12));
+		window.anchor(anchor, 10);
+		window.anchor(Timespec::new(200, 0), 20);
+		assert_eq!(window.anchor_point(), Some((anchor, 10)));
+
+		assert!(!window.contains(Timespec::new(101, 0), 11));
+		assert!(window.contains(Timespec::new(101, 0), 12));
+		assert!(window.contains(Timespec::new(110, 0), 13));
+		assert!(!window.contains(Timespec::new(110, 1), 13));
+		assert!(!window.contains(Timespec::new(101, 0), 14));
+		assert!(!window.contains(Timespec::new(99, 0), 12));
+
+		window.set_cycles(":").unwrap();
+		assert_eq!((window.first_cycle, window.last_cycle), (None, None));
+		assert!(window.set_cycles("5").is_err());
+		assert!(window.set_cycles("a:").is_err());
+	}
+
+}